@@ -0,0 +1,114 @@
+use std::io;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+
+use crate::query::PortQuery;
+use crate::snapshot::sorted;
+use crate::socket::{Protocol, Socket};
+
+/// Returns whether a new `protocol` socket could bind `addr:port` right now.
+///
+/// Unlike the port-only functions, this follows the kernel's address rules: a
+/// listener on `127.0.0.1:8080` does not stop `10.0.0.5:8080` from being
+/// bound, while one on `0.0.0.0:8080` does. See [`Socket::conflicts_with`]
+/// for the exact semantics, including dual-stack IPv6 wildcards.
+///
+/// Failure variant:
+///   * `Err(e)` – none of the backends of the default [`SourceChain`](crate::SourceChain) could be
+///     queried, or their output could not be parsed.
+pub fn is_free_on(addr: IpAddr, port: u16, protocol: Protocol) -> io::Result<bool> {
+    let free = PortQuery::new()
+        .protocol(protocol)
+        .range(port..=port)
+        .bind_addr(addr)
+        .free()
+        .run_set()?;
+    Ok(free.contains(port))
+}
+
+/// Returns the ports of `range` a new `protocol` socket could bind on `addr`.
+///
+/// Success variants:
+///   * `Ok(Some(vec))` – at least one port of `range` is free on `addr`.
+///   * `Ok(None)`      – every port of `range` is blocked for `addr`.
+///
+/// Failure variant:
+///   * `Err(e)` – none of the backends of the default [`SourceChain`](crate::SourceChain) could be
+///     queried, or their output could not be parsed.
+pub fn free_ports_on(
+    addr: IpAddr,
+    range: RangeInclusive<u16>,
+    protocol: Protocol,
+) -> io::Result<Option<Vec<u16>>> {
+    PortQuery::new()
+        .protocol(protocol)
+        .range(range)
+        .bind_addr(addr)
+        .free()
+        .run()
+}
+
+/// Sorted ports on which some `protocol` socket conflicts with `addr`.
+pub(crate) fn blocked_ports(sockets: &[Socket], addr: IpAddr, protocol: Protocol) -> Vec<u16> {
+    sorted(
+        sockets
+            .iter()
+            .filter(|socket| socket.protocol == protocol && socket.conflicts_with(addr))
+            .map(|socket| socket.port)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_ss_output;
+    use crate::snapshot::Snapshot;
+
+    const LISTENERS: &str = "LISTEN 0 128 127.0.0.1:8080 0.0.0.0:*\n\
+                             LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n\
+                             LISTEN 0 128 [::]:9090 [::]:*\n\
+                             LISTEN 0 128 *:3000 *:*\n";
+
+    #[test]
+    fn blocked_ports_depend_on_address() {
+        let sockets = parse_ss_output(LISTENERS, Protocol::Tcp);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert_eq!(
+            blocked_ports(&sockets, ip("127.0.0.1"), Protocol::Tcp),
+            vec![22, 3000, 8080]
+        );
+        assert_eq!(
+            blocked_ports(&sockets, ip("10.0.0.5"), Protocol::Tcp),
+            vec![22, 3000]
+        );
+        assert_eq!(
+            blocked_ports(&sockets, ip("::1"), Protocol::Tcp),
+            vec![3000, 9090]
+        );
+        assert_eq!(
+            blocked_ports(&sockets, ip("0.0.0.0"), Protocol::Tcp),
+            vec![22, 3000, 8080]
+        );
+        assert!(blocked_ports(&sockets, ip("10.0.0.5"), Protocol::Udp).is_empty());
+    }
+
+    #[test]
+    fn free_ports_follow_the_address() {
+        let snap = Snapshot::from_sockets(parse_ss_output(LISTENERS, Protocol::Tcp));
+        let free_on = |addr: &str| {
+            PortQuery::tcp()
+                .range(8079..=8081)
+                .bind_addr(addr.parse().unwrap())
+                .free()
+                .evaluate(&snap)
+                .to_vec()
+        };
+
+        assert_eq!(free_on("127.0.0.1"), Some(vec![8079, 8081]));
+        assert_eq!(free_on("10.0.0.5"), Some(vec![8079, 8080, 8081]));
+        assert!(!snap.is_free_on("127.0.0.1".parse().unwrap(), 3000, Protocol::Tcp));
+        assert!(snap.is_free_on("::1".parse().unwrap(), 22, Protocol::Tcp));
+    }
+}
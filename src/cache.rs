@@ -0,0 +1,372 @@
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::error::duplicate;
+use crate::portset::PortSet;
+use crate::query::PortQuery;
+use crate::snapshot::Snapshot;
+use crate::socket::Protocol;
+use crate::source::SourceChain;
+
+/// Answers port questions from a [`Snapshot`] that is reused for a fixed
+/// time, instead of scanning the host on every call.
+///
+/// The first call scans; later calls answer from the same snapshot until it
+/// is older than the TTL. When several threads miss the cache at once, one
+/// of them scans and the others wait for its snapshot, so the host is never
+/// scanned twice in parallel. The waiters get that snapshot even if the scan
+/// took longer than the TTL. Call [`CachedScanner::invalidate`] right after
+/// binding or closing a socket to make the next call see the change.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use walled::{CachedScanner, Protocol};
+///
+/// let scanner = CachedScanner::new(Duration::from_secs(1));
+/// if !scanner.is_used(Protocol::Tcp, 8080)? {
+///     // bind 8080 ...
+///     scanner.invalidate();
+/// }
+/// let privileged = scanner.privileged_tcp_used()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// If the scan fails, nothing is cached: its caller and every caller that
+/// was waiting for it get the error, and the next call scans again.
+///
+/// Only the methods of a `CachedScanner` use its snapshot. The free
+/// functions such as [`privileged_tcp_used`](crate::privileged_tcp_used) and
+/// [`PortQuery::run`] always scan the host, so share one scanner and call
+/// [`CachedScanner::evaluate`] where the scans add up.
+pub struct CachedScanner {
+    chain: SourceChain,
+    ttl: Duration,
+    state: Mutex<CacheState>,
+    scanned: Condvar,
+}
+
+#[derive(Default)]
+struct CacheState {
+    snapshot: Option<(Instant, Arc<Snapshot>)>,
+    scanning: bool,
+    /// Number of scans started so far; identifies the one in flight.
+    scan: u64,
+    /// The result of the last finished scan, handed to the callers that
+    /// waited for it.
+    outcome: Option<(u64, io::Result<Arc<Snapshot>>)>,
+    /// Bumped by [`CachedScanner::invalidate`], so that a scan started
+    /// before the invalidation is not cached.
+    generation: u64,
+}
+
+impl CachedScanner {
+    /// A scanner using the default [`SourceChain`] and keeping each snapshot
+    /// for `ttl`.
+    pub fn new(ttl: Duration) -> CachedScanner {
+        CachedScanner::with_chain(SourceChain::default(), ttl)
+    }
+
+    /// A scanner using `chain` and keeping each snapshot for `ttl`.
+    pub fn with_chain(chain: SourceChain, ttl: Duration) -> CachedScanner {
+        CachedScanner {
+            chain,
+            ttl,
+            state: Mutex::new(CacheState::default()),
+            scanned: Condvar::new(),
+        }
+    }
+
+    /// How long a snapshot is reused.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Drops the cached snapshot; the next call scans again.
+    ///
+    /// A scan already in flight still answers its callers, but its snapshot
+    /// is not kept.
+    pub fn invalidate(&self) {
+        let mut state = self.lock();
+        state.snapshot = None;
+        state.generation += 1;
+    }
+
+    /// The cached snapshot, or a new one if it expired or was invalidated.
+    pub fn snapshot(&self) -> io::Result<Arc<Snapshot>> {
+        let mut state = self.lock();
+        let mut joined = None;
+        loop {
+            if let Some((scan, outcome)) = &state.outcome
+                && joined == Some(*scan)
+            {
+                return share(outcome);
+            }
+            if let Some((taken, snapshot)) = &state.snapshot
+                && taken.elapsed() < self.ttl
+            {
+                return Ok(Arc::clone(snapshot));
+            }
+            if !state.scanning {
+                break;
+            }
+            joined = Some(state.scan);
+            state = self
+                .scanned
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+
+        state.scanning = true;
+        state.scan += 1;
+        let scan = state.scan;
+        let generation = state.generation;
+        drop(state);
+
+        let guard = ScanGuard(self);
+        let taken = Instant::now();
+        let captured = Snapshot::capture_from(&self.chain).map(Arc::new);
+
+        // Stored before the guard wakes the waiters, so they find it.
+        let mut state = self.lock();
+        if let Ok(snapshot) = &captured
+            && state.generation == generation
+        {
+            state.snapshot = Some((taken, Arc::clone(snapshot)));
+        }
+        state.outcome = Some((scan, share(&captured)));
+        drop(state);
+        drop(guard);
+        captured
+    }
+
+    /// Answers `query` from the cached snapshot; see [`PortQuery::evaluate`].
+    pub fn evaluate(&self, query: &PortQuery) -> io::Result<PortSet> {
+        Ok(query.evaluate(&*self.snapshot()?))
+    }
+
+    /// Whether anything listens on `port` for `protocol`.
+    pub fn is_used(&self, protocol: Protocol, port: u16) -> io::Result<bool> {
+        Ok(self.snapshot()?.is_used(protocol, port))
+    }
+
+    /// Whether a new `protocol` socket could bind `addr:port`.
+    pub fn is_free_on(&self, addr: IpAddr, port: u16, protocol: Protocol) -> io::Result<bool> {
+        Ok(self.snapshot()?.is_free_on(addr, port, protocol))
+    }
+
+    /// Cached counterpart of [`privileged_tcp_used`](crate::privileged_tcp_used).
+    pub fn privileged_tcp_used(&self) -> io::Result<Option<Vec<u16>>> {
+        Ok(self.snapshot()?.privileged_tcp_used())
+    }
+
+    /// Cached counterpart of [`privileged_tcp_free`](crate::privileged_tcp_free).
+    pub fn privileged_tcp_free(&self) -> io::Result<Option<Vec<u16>>> {
+        Ok(self.snapshot()?.privileged_tcp_free())
+    }
+
+    /// Cached counterpart of [`unprivileged_tcp_used`](crate::unprivileged_tcp_used).
+    pub fn unprivileged_tcp_used(&self) -> io::Result<Option<Vec<u16>>> {
+        Ok(self.snapshot()?.unprivileged_tcp_used())
+    }
+
+    /// Cached counterpart of [`unprivileged_tcp_free`](crate::unprivileged_tcp_free).
+    pub fn unprivileged_tcp_free(&self) -> io::Result<Option<Vec<u16>>> {
+        Ok(self.snapshot()?.unprivileged_tcp_free())
+    }
+
+    /// Cached counterpart of [`privileged_udp_used`](crate::privileged_udp_used).
+    pub fn privileged_udp_used(&self) -> io::Result<Option<Vec<u16>>> {
+        Ok(self.snapshot()?.privileged_udp_used())
+    }
+
+    /// Cached counterpart of [`privileged_udp_free`](crate::privileged_udp_free).
+    pub fn privileged_udp_free(&self) -> io::Result<Option<Vec<u16>>> {
+        Ok(self.snapshot()?.privileged_udp_free())
+    }
+
+    /// Cached counterpart of [`unprivileged_udp_used`](crate::unprivileged_udp_used).
+    pub fn unprivileged_udp_used(&self) -> io::Result<Option<Vec<u16>>> {
+        Ok(self.snapshot()?.unprivileged_udp_used())
+    }
+
+    /// Cached counterpart of [`unprivileged_udp_free`](crate::unprivileged_udp_free).
+    pub fn unprivileged_udp_free(&self) -> io::Result<Option<Vec<u16>>> {
+        Ok(self.snapshot()?.unprivileged_udp_free())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Another handle on the result of a scan, for one more caller.
+fn share(outcome: &io::Result<Arc<Snapshot>>) -> io::Result<Arc<Snapshot>> {
+    match outcome {
+        Ok(snapshot) => Ok(Arc::clone(snapshot)),
+        Err(err) => Err(duplicate(err)),
+    }
+}
+
+/// Clears the in-flight flag and wakes the waiting callers when the scan
+/// ends, even if it failed or panicked.
+struct ScanGuard<'a>(&'a CachedScanner);
+
+impl Drop for ScanGuard<'_> {
+    fn drop(&mut self) {
+        self.0.lock().scanning = false;
+        self.0.scanned.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::WalledError;
+    use crate::source::SsSource;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    /// A scanner over a recorded `ss` whose runs are counted; each scan runs
+    /// it twice, once per protocol.
+    fn counting(ttl: Duration, delay: Duration) -> (Arc<CachedScanner>, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let runner = move |_: &str, args: &[&str]| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(delay);
+            Ok(match args {
+                ["-tlnH"] => "LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n",
+                _ => "UNCONN 0 0 0.0.0.0:53 0.0.0.0:*\n",
+            }
+            .to_string())
+        };
+        let chain = SourceChain::new().with(SsSource::with_runner(runner));
+        (Arc::new(CachedScanner::with_chain(chain, ttl)), runs)
+    }
+
+    #[test]
+    fn answers_from_the_cache_until_invalidated() {
+        let (scanner, runs) = counting(Duration::from_secs(60), Duration::ZERO);
+
+        assert!(scanner.is_used(Protocol::Tcp, 22).unwrap());
+        assert!(!scanner.is_used(Protocol::Udp, 22).unwrap());
+        let used = scanner.evaluate(&PortQuery::udp()).unwrap();
+        assert_eq!(used.iter().collect::<Vec<_>>(), vec![53]);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        scanner.invalidate();
+        scanner.snapshot().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn expired_snapshots_are_rescanned() {
+        let (scanner, runs) = counting(Duration::ZERO, Duration::ZERO);
+        scanner.snapshot().unwrap();
+        scanner.snapshot().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn concurrent_misses_share_one_scan() {
+        let (scanner, runs) = counting(Duration::from_secs(60), Duration::from_millis(50));
+
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let scanner = Arc::clone(&scanner);
+                thread::spawn(move || scanner.snapshot().unwrap())
+            })
+            .collect();
+        let snapshots: Vec<_> = callers.into_iter().map(|c| c.join().unwrap()).collect();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert!(snapshots.iter().all(|s| Arc::ptr_eq(s, &snapshots[0])));
+    }
+
+    #[test]
+    fn waiters_share_a_scan_slower_than_the_ttl() {
+        let (scanner, runs) = counting(Duration::from_millis(10), Duration::from_millis(50));
+
+        let callers: Vec<_> = (0..4)
+            .map(|_| {
+                let scanner = Arc::clone(&scanner);
+                thread::spawn(move || scanner.snapshot().unwrap())
+            })
+            .collect();
+        let snapshots: Vec<_> = callers.into_iter().map(|c| c.join().unwrap()).collect();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert!(snapshots.iter().all(|s| Arc::ptr_eq(s, &snapshots[0])));
+    }
+
+    #[test]
+    fn invalidation_mid_scan_still_answers_the_waiters() {
+        let (scanner, runs) = counting(Duration::from_secs(60), Duration::from_millis(50));
+        let snapshot = |scanner: &Arc<CachedScanner>| {
+            let scanner = Arc::clone(scanner);
+            thread::spawn(move || scanner.snapshot().unwrap())
+        };
+
+        let leader = snapshot(&scanner);
+        thread::sleep(Duration::from_millis(10));
+        let waiters: Vec<_> = (0..4).map(|_| snapshot(&scanner)).collect();
+        thread::sleep(Duration::from_millis(20));
+        scanner.invalidate();
+
+        let first = leader.join().unwrap();
+        for waiter in waiters {
+            assert!(Arc::ptr_eq(&waiter.join().unwrap(), &first));
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        // The invalidated snapshot was not kept.
+        assert!(!Arc::ptr_eq(&scanner.snapshot().unwrap(), &first));
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn concurrent_misses_share_a_failed_scan() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let runner = move |_: &str, _: &[&str]| -> io::Result<String> {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            Err(io::Error::new(io::ErrorKind::NotFound, "no ss"))
+        };
+        let chain = SourceChain::new().with(SsSource::with_runner(runner));
+        let scanner = Arc::new(CachedScanner::with_chain(chain, Duration::from_secs(60)));
+
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let scanner = Arc::clone(&scanner);
+                thread::spawn(move || scanner.snapshot().unwrap_err())
+            })
+            .collect();
+        for caller in callers {
+            let err = caller.join().unwrap();
+            assert!(matches!(
+                WalledError::of(&err),
+                Some(WalledError::NoSourceAnswered { .. })
+            ));
+        }
+
+        // The chain stops at the first failed protocol.
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn failed_scans_are_not_cached() {
+        let chain = SourceChain::new().with(SsSource::with_runner(
+            |_: &str, _: &[&str]| -> io::Result<String> {
+                Err(io::Error::new(io::ErrorKind::NotFound, "no ss"))
+            },
+        ));
+        let scanner = CachedScanner::with_chain(chain, Duration::from_secs(60));
+        assert!(scanner.snapshot().is_err());
+        assert!(scanner.privileged_tcp_used().is_err());
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How often blocking waits check their deadline and cancellation handle.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A shareable flag that stops scans in progress.
+///
+/// Clones share the same flag. Hand one to a [`SystemRunner`](crate::SystemRunner)
+/// or a [`SourceChain`](crate::SourceChain) and call [`CancelHandle::cancel`]
+/// from any thread: running `ss` children are killed, other backends are
+/// abandoned, and the scan fails with
+/// [`WalledError::Cancelled`](crate::WalledError::Cancelled). A cancelled
+/// handle stays cancelled, so later scans using it fail straight away.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    /// A handle that is not cancelled yet.
+    pub fn new() -> CancelHandle {
+        CancelHandle::default()
+    }
+
+    /// Cancels every scan using this handle or one of its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [`CancelHandle::cancel`] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_flag() {
+        let handle = CancelHandle::new();
+        let clone = handle.clone();
+        assert!(!clone.is_cancelled());
+        handle.cancel();
+        assert!(clone.is_cancelled());
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::socket::{Family, Protocol, Socket};
+
+/// A socket present in both snapshots of a [`SnapshotDiff`] whose bind
+/// address or owner changed in between.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SocketChange {
+    pub before: Socket,
+    pub after: Socket,
+}
+
+impl SocketChange {
+    /// Whether the socket is now bound to another address, e.g. moved from
+    /// `127.0.0.1` to `0.0.0.0`.
+    pub fn addr_changed(&self) -> bool {
+        self.before.local_addr != self.after.local_addr
+    }
+
+    /// Whether another process owns the socket: its inode or UID differs.
+    ///
+    /// Only known when both backends report them; `ss` does not.
+    pub fn owner_changed(&self) -> bool {
+        !same_owner(&self.before, &self.after)
+    }
+}
+
+/// The differences between two snapshots for one protocol.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ProtocolDiff {
+    /// Sockets only found in the later snapshot.
+    pub added: Vec<Socket>,
+    /// Sockets only found in the earlier snapshot.
+    pub removed: Vec<Socket>,
+    /// Sockets found in both, with another bind address or owner.
+    pub changed: Vec<SocketChange>,
+}
+
+impl ProtocolDiff {
+    /// Whether nothing was added, removed or changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// What changed between two snapshots, grouped by protocol; see
+/// [`Snapshot::diff`](crate::Snapshot::diff).
+///
+/// Sockets are matched by family, port and peer. Among those, a socket
+/// with the same address and owner is unchanged; one with the same address
+/// but another owner, or the last one left on both sides, counts as
+/// changed; the rest are added or removed. Queue sizes and other volatile
+/// fields are ignored. Each list is sorted by port, family and address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SnapshotDiff {
+    pub tcp: ProtocolDiff,
+    pub udp: ProtocolDiff,
+}
+
+impl SnapshotDiff {
+    /// The differences for `protocol`.
+    pub fn protocol(&self, protocol: Protocol) -> &ProtocolDiff {
+        match protocol {
+            Protocol::Tcp => &self.tcp,
+            Protocol::Udp => &self.udp,
+        }
+    }
+
+    /// Whether the two snapshots hold the same sockets.
+    pub fn is_empty(&self) -> bool {
+        self.tcp.is_empty() && self.udp.is_empty()
+    }
+}
+
+/// Where a socket is matched against its counterpart.
+type Slot = (Family, u16, Option<SocketAddr>);
+
+pub(crate) fn diff(before: &[Socket], after: &[Socket]) -> SnapshotDiff {
+    SnapshotDiff {
+        tcp: diff_protocol(before, after, Protocol::Tcp),
+        udp: diff_protocol(before, after, Protocol::Udp),
+    }
+}
+
+fn diff_protocol(before: &[Socket], after: &[Socket], protocol: Protocol) -> ProtocolDiff {
+    let mut slots: BTreeMap<Slot, (Vec<&Socket>, Vec<&Socket>)> = BTreeMap::new();
+    for socket in before.iter().filter(|s| s.protocol == protocol) {
+        slots.entry(slot(socket)).or_default().0.push(socket);
+    }
+    for socket in after.iter().filter(|s| s.protocol == protocol) {
+        slots.entry(slot(socket)).or_default().1.push(socket);
+    }
+
+    let mut diff = ProtocolDiff::default();
+    for (mut old, mut new) in slots.into_values() {
+        // Unchanged sockets, then owner changes, then address changes.
+        take_pairs(&mut old, &mut new, |a, b| {
+            a.local_addr == b.local_addr && same_owner(a, b)
+        });
+        for (before, after) in take_pairs(&mut old, &mut new, |a, b| a.local_addr == b.local_addr) {
+            diff.changed.push(SocketChange { before, after });
+        }
+        if old.len() == 1 && new.len() == 1 {
+            diff.changed.push(SocketChange {
+                before: old.remove(0).clone(),
+                after: new.remove(0).clone(),
+            });
+        }
+        diff.removed.extend(old.into_iter().cloned());
+        diff.added.extend(new.into_iter().cloned());
+    }
+
+    diff.added.sort_by_key(order);
+    diff.removed.sort_by_key(order);
+    diff.changed.sort_by_key(|change| order(&change.before));
+    diff
+}
+
+/// Removes and returns, cloned, the pairs of `old` and `new` sockets for
+/// which `same` holds; each socket is used at most once.
+fn take_pairs(
+    old: &mut Vec<&Socket>,
+    new: &mut Vec<&Socket>,
+    same: impl Fn(&Socket, &Socket) -> bool,
+) -> Vec<(Socket, Socket)> {
+    let mut pairs = Vec::new();
+    let mut index = 0;
+    while index < old.len() {
+        match new.iter().position(|candidate| same(old[index], candidate)) {
+            Some(found) => {
+                let after = new.remove(found);
+                let before = old.remove(index);
+                pairs.push((before.clone(), after.clone()));
+            }
+            None => index += 1,
+        }
+    }
+    pairs
+}
+
+fn slot(socket: &Socket) -> Slot {
+    (socket.family, socket.port, socket.remote)
+}
+
+fn order(socket: &Socket) -> (u16, Family, IpAddr) {
+    (socket.port, socket.family, socket.local_addr)
+}
+
+/// Whether two sockets have the same owner, as far as both sides tell.
+fn same_owner(a: &Socket, b: &Socket) -> bool {
+    a.uid.zip(b.uid).is_none_or(|(x, y)| x == y) && a.inode.zip(b.inode).is_none_or(|(x, y)| x == y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_ss_output;
+
+    fn tcp(text: &str) -> Vec<Socket> {
+        parse_ss_output(text, Protocol::Tcp)
+    }
+
+    fn owned(mut socket: Socket, inode: u64) -> Socket {
+        socket.inode = Some(inode);
+        socket.uid = Some(1000);
+        socket
+    }
+
+    #[test]
+    fn identical_snapshots_have_no_diff() {
+        let sockets = tcp("LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n");
+        let mut busier = sockets.clone();
+        busier[0].recv_q = 5;
+        assert!(diff(&sockets, &busier).is_empty());
+    }
+
+    #[test]
+    fn added_removed_and_rebound() {
+        let before = tcp("LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n\
+             LISTEN 0 128 127.0.0.1:8080 0.0.0.0:*\n\
+             LISTEN 0 128 127.0.0.1:9000 0.0.0.0:*\n");
+        let mut after = tcp("LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n\
+             LISTEN 0 128 0.0.0.0:8080 0.0.0.0:*\n\
+             LISTEN 0 128 [::]:443 [::]:*\n");
+        after.extend(parse_ss_output(
+            "UNCONN 0 0 0.0.0.0:53 0.0.0.0:*\n",
+            Protocol::Udp,
+        ));
+
+        let diff = diff(&before, &after);
+        assert_eq!(diff.tcp.added.len(), 1);
+        assert_eq!(diff.tcp.added[0].port, 443);
+        assert_eq!(diff.tcp.removed.len(), 1);
+        assert_eq!(diff.tcp.removed[0].port, 9000);
+
+        assert_eq!(diff.tcp.changed.len(), 1);
+        let change = &diff.tcp.changed[0];
+        assert_eq!(change.before.local().to_string(), "127.0.0.1:8080");
+        assert_eq!(change.after.local().to_string(), "0.0.0.0:8080");
+        assert!(change.addr_changed() && !change.owner_changed());
+
+        assert_eq!(diff.protocol(Protocol::Udp).added.len(), 1);
+        assert!(diff.udp.removed.is_empty());
+    }
+
+    #[test]
+    fn owner_changes_are_detected() {
+        let base = tcp("LISTEN 0 128 0.0.0.0:5432 0.0.0.0:*\n\
+             LISTEN 0 128 [::]:5432 [::]:*\n");
+        let before = vec![owned(base[0].clone(), 100), owned(base[1].clone(), 101)];
+        let after = vec![owned(base[0].clone(), 200), owned(base[1].clone(), 101)];
+
+        let diff = diff(&before, &after);
+        assert!(diff.tcp.added.is_empty() && diff.tcp.removed.is_empty());
+        assert_eq!(diff.tcp.changed.len(), 1);
+        assert_eq!(diff.tcp.changed[0].before.family, Family::V4);
+        assert!(diff.tcp.changed[0].owner_changed());
+        assert!(!diff.tcp.changed[0].addr_changed());
+
+        // Without owner information on one side nothing changed.
+        assert!(super::diff(&before, &base).is_empty());
+    }
+
+    #[test]
+    fn extra_sockets_on_a_port_are_added() {
+        let before = tcp("LISTEN 0 128 127.0.0.1:80 0.0.0.0:*\n");
+        let after = tcp("LISTEN 0 128 127.0.0.1:80 0.0.0.0:*\n\
+             LISTEN 0 128 10.0.0.5:80 0.0.0.0:*\n");
+
+        let diff = diff(&before, &after);
+        assert!(diff.tcp.changed.is_empty());
+        assert_eq!(diff.tcp.added.len(), 1);
+        assert_eq!(diff.tcp.added[0].local().to_string(), "10.0.0.5:80");
+    }
+}
@@ -0,0 +1,273 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// How a version of `ss` prints addresses, which decides what `*` means.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SsDialect {
+    /// Current iproute2: IPv6 in brackets (`[::]:22`), the IPv4 wildcard
+    /// spelled out (`0.0.0.0:22`) and `*` for a dual-stack IPv6 socket.
+    #[default]
+    Bracketed,
+    /// Older iproute2: IPv6 unbracketed (`:::22`) and `*` for the IPv4
+    /// wildcard `0.0.0.0`.
+    Legacy,
+}
+
+impl SsDialect {
+    /// Tells the dialect from the address columns of a whole `ss` output.
+    ///
+    /// An unbracketed IPv6 address, or an IPv4 address whose peer is `*`,
+    /// only occur in the legacy dialect. Output holding only `*` hosts does
+    /// not tell; it is read as [`SsDialect::Bracketed`].
+    pub fn detect(output: &str) -> SsDialect {
+        for line in output.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let (Some(local), peer) = (parts.get(3), parts.get(4)) else {
+                continue;
+            };
+            let Some(endpoint) = parse_ss_endpoint(local) else {
+                continue;
+            };
+            let peer = peer.and_then(|peer| parse_ss_endpoint(peer));
+            match endpoint.host {
+                SsHost::Ip(IpAddr::V6(_)) if !local.starts_with('[') => return SsDialect::Legacy,
+                SsHost::Ip(IpAddr::V6(_)) => return SsDialect::Bracketed,
+                SsHost::Ip(IpAddr::V4(_)) => {
+                    return match peer.map(|peer| peer.host) {
+                        Some(SsHost::Any) => SsDialect::Legacy,
+                        _ => SsDialect::Bracketed,
+                    };
+                }
+                SsHost::Any => {}
+            }
+        }
+        SsDialect::Bracketed
+    }
+}
+
+/// Host part of an `ss` address column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SsHost {
+    /// `*`: a wildcard address, or any peer. Which family it stands for
+    /// depends on the [`SsDialect`].
+    Any,
+    /// A literal IPv4 or IPv6 address.
+    Ip(IpAddr),
+}
+
+impl SsHost {
+    /// The address this host stands for, reading `*` as `::` in the
+    /// bracketed dialect and as `0.0.0.0` in the legacy one.
+    pub fn ip(&self, dialect: SsDialect) -> IpAddr {
+        match (self, dialect) {
+            (SsHost::Any, SsDialect::Bracketed) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            (SsHost::Any, SsDialect::Legacy) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (SsHost::Ip(addr), _) => *addr,
+        }
+    }
+}
+
+/// An address column of `ss -n` output, e.g. `[fe80::1]%eth0:546`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SsEndpoint {
+    pub host: SsHost,
+    /// Interface the socket is bound to (`%lo`), without the `%`.
+    pub scope: Option<String>,
+    /// Port, or `None` for `*` as printed for unconnected peers.
+    pub port: Option<u16>,
+}
+
+/// Parses an address column as printed by `ss -n`.
+///
+/// Every form emitted by iproute2 is accepted:
+///
+///   * `0.0.0.0:22`, `127.0.0.1:631`
+///   * `127.0.0.53%lo:53`, `0.0.0.0%eth0:68` (bound to a device)
+///   * `[::]:22`, `[::1]:631`, `[::ffff:127.0.0.1]:80`
+///   * `[fe80::1]%eth0:546`
+///   * `*:68`, `*:*`, `0.0.0.0:*`, `[::]:*`
+///   * `:::22`, `::1:631`, `fe80::1%eth0:546` (unbracketed, older iproute2)
+///
+/// Older iproute2 also prints `*:22` for `0.0.0.0:22`, so the family of a
+/// `*` host depends on the [`SsDialect`] of the whole output.
+///
+/// Returns `None` for anything else.
+pub fn parse_ss_endpoint(field: &str) -> Option<SsEndpoint> {
+    let (rest, port) = field.rsplit_once(':')?;
+    let port = match port {
+        "*" => None,
+        digits => Some(digits.parse().ok()?),
+    };
+
+    let (host, scope) = match rest.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']')?;
+            let scope = match after {
+                "" => None,
+                _ => Some(after.strip_prefix('%')?),
+            };
+            (host, scope)
+        }
+        None => match rest.split_once('%') {
+            Some((host, scope)) => (host, Some(scope)),
+            None => (rest, None),
+        },
+    };
+
+    if scope.is_some_and(|scope| scope.is_empty()) {
+        return None;
+    }
+
+    let host = match host {
+        "*" => SsHost::Any,
+        literal => SsHost::Ip(literal.parse().ok()?),
+    };
+
+    Some(SsEndpoint {
+        host,
+        scope: scope.map(str::to_string),
+        port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(host: SsHost, scope: Option<&str>, port: Option<u16>) -> SsEndpoint {
+        SsEndpoint {
+            host,
+            scope: scope.map(str::to_string),
+            port,
+        }
+    }
+
+    fn ip(addr: &str) -> SsHost {
+        SsHost::Ip(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn ipv4_forms() {
+        assert_eq!(
+            parse_ss_endpoint("0.0.0.0:22"),
+            Some(endpoint(ip("0.0.0.0"), None, Some(22)))
+        );
+        assert_eq!(
+            parse_ss_endpoint("127.0.0.53%lo:53"),
+            Some(endpoint(ip("127.0.0.53"), Some("lo"), Some(53)))
+        );
+        assert_eq!(
+            parse_ss_endpoint("0.0.0.0%eth0:68"),
+            Some(endpoint(ip("0.0.0.0"), Some("eth0"), Some(68)))
+        );
+        assert_eq!(
+            parse_ss_endpoint("0.0.0.0:*"),
+            Some(endpoint(ip("0.0.0.0"), None, None))
+        );
+    }
+
+    #[test]
+    fn bracketed_ipv6_forms() {
+        assert_eq!(
+            parse_ss_endpoint("[::]:22"),
+            Some(endpoint(ip("::"), None, Some(22)))
+        );
+        assert_eq!(
+            parse_ss_endpoint("[::1]:631"),
+            Some(endpoint(ip("::1"), None, Some(631)))
+        );
+        assert_eq!(
+            parse_ss_endpoint("[::ffff:127.0.0.1]:80"),
+            Some(endpoint(ip("::ffff:127.0.0.1"), None, Some(80)))
+        );
+        assert_eq!(
+            parse_ss_endpoint("[fe80::5054:ff:fe12:3456]%eth0:546"),
+            Some(endpoint(
+                ip("fe80::5054:ff:fe12:3456"),
+                Some("eth0"),
+                Some(546)
+            ))
+        );
+        assert_eq!(
+            parse_ss_endpoint("[::]:*"),
+            Some(endpoint(ip("::"), None, None))
+        );
+    }
+
+    #[test]
+    fn wildcard_forms() {
+        let any = parse_ss_endpoint("*:68").unwrap();
+        assert_eq!(any, endpoint(SsHost::Any, None, Some(68)));
+        assert_eq!(
+            any.host.ip(SsDialect::Bracketed),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        );
+        assert_eq!(
+            any.host.ip(SsDialect::Legacy),
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        );
+        assert_eq!(
+            parse_ss_endpoint("*:*"),
+            Some(endpoint(SsHost::Any, None, None))
+        );
+    }
+
+    #[test]
+    fn unbracketed_ipv6_forms() {
+        assert_eq!(
+            parse_ss_endpoint(":::22"),
+            Some(endpoint(ip("::"), None, Some(22)))
+        );
+        assert_eq!(
+            parse_ss_endpoint("::1:631"),
+            Some(endpoint(ip("::1"), None, Some(631)))
+        );
+        assert_eq!(
+            parse_ss_endpoint("fe80::1%eth0:546"),
+            Some(endpoint(ip("fe80::1"), Some("eth0"), Some(546)))
+        );
+    }
+
+    #[test]
+    fn detects_the_dialect() {
+        let current = "LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\nLISTEN 0 128 *:9100 *:*\n";
+        assert_eq!(SsDialect::detect(current), SsDialect::Bracketed);
+        assert_eq!(
+            SsDialect::detect("LISTEN 0 128 [::1]:631 [::]:*\n"),
+            SsDialect::Bracketed
+        );
+
+        let legacy = "State Recv-Q Send-Q Local Address:Port Peer Address:Port\n\
+                      LISTEN 0 128 *:22 *:*\n\
+                      LISTEN 0 128 :::22 :::*\n";
+        assert_eq!(SsDialect::detect(legacy), SsDialect::Legacy);
+        // Without IPv6, a specific IPv4 listener with a `*` peer tells.
+        assert_eq!(
+            SsDialect::detect("LISTEN 0 128 *:22 *:*\nLISTEN 0 100 127.0.0.1:25 *:*\n"),
+            SsDialect::Legacy
+        );
+
+        assert_eq!(
+            SsDialect::detect("LISTEN 0 128 *:22 *:*\n"),
+            SsDialect::Bracketed
+        );
+        assert_eq!(SsDialect::detect(""), SsDialect::Bracketed);
+    }
+
+    #[test]
+    fn rejects_malformed_columns() {
+        for bad in [
+            "",
+            "22",
+            "0.0.0.0",
+            "0.0.0.0:ssh",
+            "0.0.0.0:70000",
+            "[::1:22",
+            "[::1]x:22",
+            "127.0.0.1%:53",
+            "host:22",
+            "[::1]%:22",
+        ] {
+            assert_eq!(parse_ss_endpoint(bad), None, "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// What went wrong while scanning, as a matchable value.
+///
+/// Every fallible function of this crate returns [`io::Result`] so existing
+/// callers keep working; the `io::Error` wraps one of these variants and
+/// keeps the matching [`io::ErrorKind`]. Recover it with [`WalledError::of`]:
+///
+/// ```no_run
+/// use walled::{SourceChain, SsSource, WalledError, Protocol};
+///
+/// let chain = SourceChain::new().with(SsSource::new());
+/// if let Err(e) = chain.listening_sockets(Protocol::Tcp) {
+///     if let Some(WalledError::NoSourceAnswered { failures }) = WalledError::of(&e) {
+///         for (source, failure) in failures {
+///             match WalledError::of(failure) {
+///                 Some(WalledError::BackendNotFound { .. }) => println!("{} is missing", source),
+///                 Some(WalledError::BackendFailed { stderr, .. }) => println!("{} crashed: {}", source, stderr),
+///                 _ => println!("{} failed: {}", source, failure),
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub enum WalledError {
+    /// The backend is not available on this host: `ss` is not installed,
+    /// procfs is not mounted, ...
+    BackendNotFound { backend: String },
+    /// The backend ran but failed, e.g. `ss` exited with a non-zero status.
+    BackendFailed {
+        backend: String,
+        /// Exit code, or `None` if the process was killed by a signal.
+        status: Option<i32>,
+        /// What the process wrote to standard error, trimmed.
+        stderr: String,
+    },
+    /// Backend output or a sysctl value did not have the expected shape.
+    Parse {
+        /// 1-based line number within the parsed text.
+        line_no: usize,
+        line: String,
+    },
+    /// The process lacks the privileges for `operation`.
+    PermissionDenied { operation: String },
+    /// The running kernel does not support `operation`.
+    Unsupported { operation: String },
+    /// The backend did not answer within `timeout`; an `ss` child is killed,
+    /// other backends are abandoned.
+    TimedOut { backend: String, timeout: Duration },
+    /// The scan was stopped through a [`CancelHandle`](crate::CancelHandle).
+    Cancelled { backend: String },
+    /// Every backend of a [`SourceChain`](crate::SourceChain) failed; each
+    /// failure is listed with the name of its backend, in the order tried.
+    NoSourceAnswered {
+        failures: Vec<(&'static str, io::Error)>,
+    },
+}
+
+impl WalledError {
+    /// The [`WalledError`] carried by `err`, if it came from this crate.
+    pub fn of(err: &io::Error) -> Option<&WalledError> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    /// The [`io::ErrorKind`] this error is reported with.
+    ///
+    /// [`WalledError::NoSourceAnswered`] takes the kind of the last failure.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            WalledError::BackendNotFound { .. } => io::ErrorKind::NotFound,
+            WalledError::BackendFailed { .. } => io::ErrorKind::Other,
+            WalledError::Parse { .. } => io::ErrorKind::InvalidData,
+            WalledError::PermissionDenied { .. } => io::ErrorKind::PermissionDenied,
+            WalledError::Unsupported { .. } => io::ErrorKind::Unsupported,
+            WalledError::TimedOut { .. } => io::ErrorKind::TimedOut,
+            WalledError::Cancelled { .. } => io::ErrorKind::Interrupted,
+            WalledError::NoSourceAnswered { failures } => failures
+                .last()
+                .map_or(io::ErrorKind::NotFound, |(_, e)| e.kind()),
+        }
+    }
+}
+
+impl WalledError {
+    fn duplicate(&self) -> WalledError {
+        match self {
+            WalledError::BackendNotFound { backend } => WalledError::BackendNotFound {
+                backend: backend.clone(),
+            },
+            WalledError::BackendFailed {
+                backend,
+                status,
+                stderr,
+            } => WalledError::BackendFailed {
+                backend: backend.clone(),
+                status: *status,
+                stderr: stderr.clone(),
+            },
+            WalledError::Parse { line_no, line } => WalledError::Parse {
+                line_no: *line_no,
+                line: line.clone(),
+            },
+            WalledError::PermissionDenied { operation } => WalledError::PermissionDenied {
+                operation: operation.clone(),
+            },
+            WalledError::Unsupported { operation } => WalledError::Unsupported {
+                operation: operation.clone(),
+            },
+            WalledError::TimedOut { backend, timeout } => WalledError::TimedOut {
+                backend: backend.clone(),
+                timeout: *timeout,
+            },
+            WalledError::Cancelled { backend } => WalledError::Cancelled {
+                backend: backend.clone(),
+            },
+            WalledError::NoSourceAnswered { failures } => WalledError::NoSourceAnswered {
+                failures: failures
+                    .iter()
+                    .map(|(source, e)| (*source, duplicate(e)))
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// A copy of `err` for another caller of the same scan. A [`WalledError`]
+/// is copied variant by variant, so [`WalledError::of`] still matches; any
+/// other error keeps its kind and message, or its OS error code.
+pub(crate) fn duplicate(err: &io::Error) -> io::Error {
+    if let Some(walled) = WalledError::of(err) {
+        return walled.duplicate().into();
+    }
+    match err.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(err.kind(), err.to_string()),
+    }
+}
+
+impl fmt::Display for WalledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalledError::BackendNotFound { backend } => {
+                write!(f, "`{}` is not available on this host", backend)
+            }
+            WalledError::BackendFailed {
+                backend,
+                status,
+                stderr,
+            } => {
+                match status {
+                    Some(code) => write!(f, "`{}` exited with status {}", backend, code)?,
+                    None => write!(f, "`{}` was killed by a signal", backend)?,
+                }
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr)?;
+                }
+                Ok(())
+            }
+            WalledError::Parse { line_no, line } => {
+                write!(f, "unexpected input on line {}: {:?}", line_no, line)
+            }
+            WalledError::PermissionDenied { operation } => {
+                write!(f, "permission denied: {}", operation)
+            }
+            WalledError::Unsupported { operation } => {
+                write!(f, "not supported by this kernel: {}", operation)
+            }
+            WalledError::TimedOut { backend, timeout } => {
+                write!(f, "`{}` did not answer within {:?}", backend, timeout)
+            }
+            WalledError::Cancelled { backend } => write!(f, "`{}` was cancelled", backend),
+            WalledError::NoSourceAnswered { failures } => {
+                if failures.is_empty() {
+                    return f.write_str("no socket source answered (the chain is empty)");
+                }
+                f.write_str("no socket source answered (")?;
+                for (index, (source, e)) in failures.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str("; ")?;
+                    }
+                    write!(f, "{}: {}", source, e)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+impl Error for WalledError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WalledError::NoSourceAnswered { failures } => {
+                failures.last().map(|(_, e)| e as &(dyn Error + 'static))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<WalledError> for io::Error {
+    fn from(err: WalledError) -> io::Error {
+        io::Error::new(err.kind(), err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_io_error() {
+        let err: io::Error = WalledError::BackendNotFound {
+            backend: "ss".to_string(),
+        }
+        .into();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::BackendNotFound { backend }) if backend == "ss"
+        ));
+        assert_eq!(err.to_string(), "`ss` is not available on this host");
+
+        let plain = io::Error::new(io::ErrorKind::NotFound, "elsewhere");
+        assert!(WalledError::of(&plain).is_none());
+    }
+
+    #[test]
+    fn duplicates_stay_matchable() {
+        let err: io::Error = WalledError::NoSourceAnswered {
+            failures: vec![
+                ("procfs", io::Error::from_raw_os_error(2)),
+                (
+                    "ss",
+                    WalledError::TimedOut {
+                        backend: "ss".to_string(),
+                        timeout: Duration::from_secs(1),
+                    }
+                    .into(),
+                ),
+            ],
+        }
+        .into();
+
+        let copy = duplicate(&err);
+        assert_eq!(copy.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(copy.to_string(), err.to_string());
+        let Some(WalledError::NoSourceAnswered { failures }) = WalledError::of(&copy) else {
+            panic!("not a WalledError: {:?}", copy);
+        };
+        assert_eq!(failures[0].1.raw_os_error(), Some(2));
+        assert!(matches!(
+            WalledError::of(&failures[1].1),
+            Some(WalledError::TimedOut { .. })
+        ));
+
+        let plain = duplicate(&io::Error::new(io::ErrorKind::InvalidData, "bad"));
+        assert_eq!(plain.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(plain.to_string(), "bad");
+    }
+
+    #[test]
+    fn messages_and_kinds() {
+        let failed = WalledError::BackendFailed {
+            backend: "ss".to_string(),
+            status: Some(1),
+            stderr: "Cannot open netlink socket".to_string(),
+        };
+        assert_eq!(failed.kind(), io::ErrorKind::Other);
+        assert_eq!(
+            failed.to_string(),
+            "`ss` exited with status 1: Cannot open netlink socket"
+        );
+
+        let parse = WalledError::Parse {
+            line_no: 3,
+            line: "garbage".to_string(),
+        };
+        assert_eq!(parse.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(parse.to_string(), "unexpected input on line 3: \"garbage\"");
+
+        let none = WalledError::NoSourceAnswered {
+            failures: vec![
+                ("procfs", io::Error::new(io::ErrorKind::NotFound, "gone")),
+                (
+                    "ss",
+                    WalledError::Unsupported {
+                        operation: "x".to_string(),
+                    }
+                    .into(),
+                ),
+            ],
+        };
+        assert_eq!(none.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(
+            none.to_string(),
+            "no socket source answered (procfs: gone; ss: not supported by this kernel: x)"
+        );
+        assert!(none.source().is_some());
+
+        let timed_out = WalledError::TimedOut {
+            backend: "ss".to_string(),
+            timeout: Duration::from_millis(250),
+        };
+        assert_eq!(timed_out.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(timed_out.to_string(), "`ss` did not answer within 250ms");
+
+        let cancelled = WalledError::Cancelled {
+            backend: "procfs".to_string(),
+        };
+        assert_eq!(cancelled.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(cancelled.to_string(), "`procfs` was cancelled");
+    }
+}
@@ -0,0 +1,818 @@
+use std::fmt::{self, Write};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::error::WalledError;
+use crate::portset::PortSet;
+use crate::ranges::PortRanges;
+use crate::snapshot::Snapshot;
+use crate::socket::{Family, Protocol, Socket, SocketState};
+use crate::sysctl::PortLayout;
+
+/// Version of the JSON documents written by [`Snapshot::to_json`] and
+/// [`sockets_to_json`].
+///
+/// Every document is an object with a `"version"` member. Version 1 looks
+/// like this (shown indented; the encoder writes a single line):
+///
+/// ```text
+/// {
+///   "version": 1,
+///   "captured_at_ms": 1700000000000,
+///   "layout": {
+///     "unprivileged_port_start": 1024,
+///     "ephemeral": [32768, 60999],
+///     "reserved": "8000-8100,9090"
+///   },
+///   "sockets": [
+///     {
+///       "protocol": "tcp", "family": "v4",
+///       "local_addr": "0.0.0.0", "port": 22, "remote": null,
+///       "state": "LISTEN", "recv_q": 0, "send_q": 128,
+///       "inode": 4711, "uid": 0, "v6only": null, "interface": null
+///     }
+///   ]
+/// }
+/// ```
+///
+/// A socket list holds only `"version"` and `"sockets"`. Two smaller
+/// documents answer port questions: [`ports_to_json`] writes
+/// `{"version": 1, "ports": [22, 80]}` and [`port_status_to_json`] writes
+/// `{"version": 1, "port": 8080, "free": true}`. `"state"` is the
+/// name `ss` prints, or the kernel's numeric code for states this crate does
+/// not know; `"remote"` is an `addr:port` string; unknown values are `null`.
+/// Decoders ignore members they do not know, so fields may be added without
+/// a version bump; removing or changing one bumps the version.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Encodes `sockets` as a versioned JSON document.
+pub fn sockets_to_json(sockets: &[Socket]) -> String {
+    Value::Object(vec![
+        ("version", Value::number(JSON_SCHEMA_VERSION)),
+        ("sockets", sockets_value(sockets)),
+    ])
+    .to_string()
+}
+
+/// Encodes `ports`, in ascending order, as a versioned JSON document.
+pub fn ports_to_json(ports: &PortSet) -> String {
+    Value::Object(vec![
+        ("version", Value::number(JSON_SCHEMA_VERSION)),
+        (
+            "ports",
+            Value::Array(ports.iter().map(Value::number).collect()),
+        ),
+    ])
+    .to_string()
+}
+
+/// Encodes whether `port` is free as a versioned JSON document.
+pub fn port_status_to_json(port: u16, free: bool) -> String {
+    Value::Object(vec![
+        ("version", Value::number(JSON_SCHEMA_VERSION)),
+        ("port", Value::number(port)),
+        ("free", Value::Bool(free)),
+    ])
+    .to_string()
+}
+
+/// Decodes a document written by [`sockets_to_json`].
+///
+/// Failure variants:
+///   * [`WalledError::Parse`] – the text is not JSON or does not follow the
+///     schema; the line is the one holding the offending value.
+///   * [`WalledError::Unsupported`] – the document has a newer schema
+///     version than this crate knows.
+pub fn sockets_from_json(text: &str) -> io::Result<Vec<Socket>> {
+    let document = Decoder::new(text).document()?;
+    document.field("sockets")?.sockets()
+}
+
+pub(crate) fn encode_snapshot(snapshot: &Snapshot) -> String {
+    let captured_at = snapshot
+        .captured_at()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let layout = snapshot.layout();
+    let ephemeral = layout.ephemeral();
+
+    Value::Object(vec![
+        ("version", Value::number(JSON_SCHEMA_VERSION)),
+        (
+            "captured_at_ms",
+            Value::Number(captured_at.as_millis().to_string()),
+        ),
+        (
+            "layout",
+            Value::Object(vec![
+                (
+                    "unprivileged_port_start",
+                    Value::number(layout.unprivileged_port_start()),
+                ),
+                (
+                    "ephemeral",
+                    Value::Array(vec![
+                        Value::number(*ephemeral.start()),
+                        Value::number(*ephemeral.end()),
+                    ]),
+                ),
+                ("reserved", Value::String(layout.reserved().to_string())),
+            ]),
+        ),
+        ("sockets", sockets_value(snapshot.sockets())),
+    ])
+    .to_string()
+}
+
+pub(crate) fn decode_snapshot(text: &str) -> io::Result<Snapshot> {
+    let document = Decoder::new(text).document()?;
+
+    let millis = document.field("captured_at_ms")?.number::<u64>()?;
+    let captured_at = UNIX_EPOCH + Duration::from_millis(millis);
+
+    let layout = document.field("layout")?;
+    let ephemeral = layout.field("ephemeral")?;
+    let bounds = ephemeral.array()?;
+    let [start, end] = bounds else {
+        return Err(ephemeral.invalid());
+    };
+    let reserved = layout.field("reserved")?;
+    let layout = PortLayout::new(
+        layout.field("unprivileged_port_start")?.number()?,
+        start.number()?..=end.number()?,
+    )
+    .with_reserved(
+        reserved
+            .string()?
+            .parse::<PortRanges>()
+            .map_err(|_| reserved.invalid())?,
+    );
+
+    let sockets = document.field("sockets")?.sockets()?;
+    Ok(Snapshot::from_sockets(sockets)
+        .with_layout(layout)
+        .with_captured_at(captured_at))
+}
+
+fn sockets_value(sockets: &[Socket]) -> Value {
+    Value::Array(sockets.iter().map(socket_value).collect())
+}
+
+fn socket_value(socket: &Socket) -> Value {
+    let state = match socket.state {
+        SocketState::Unknown(code) => Value::number(code),
+        known => Value::String(known.to_string()),
+    };
+    let family = match socket.family {
+        Family::V4 => "v4",
+        Family::V6 => "v6",
+    };
+
+    Value::Object(vec![
+        ("protocol", Value::String(socket.protocol.to_string())),
+        ("family", Value::String(family.to_string())),
+        ("local_addr", Value::String(socket.local_addr.to_string())),
+        ("port", Value::number(socket.port)),
+        (
+            "remote",
+            Value::option(socket.remote.map(|r| Value::String(r.to_string()))),
+        ),
+        ("state", state),
+        ("recv_q", Value::number(socket.recv_q)),
+        ("send_q", Value::number(socket.send_q)),
+        ("inode", Value::option(socket.inode.map(Value::number))),
+        ("uid", Value::option(socket.uid.map(Value::number))),
+        ("v6only", Value::option(socket.v6only.map(Value::Bool))),
+        (
+            "interface",
+            Value::option(socket.interface.clone().map(Value::String)),
+        ),
+    ])
+}
+
+/// A JSON value to encode; object members keep their order.
+enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(&'static str, Value)>),
+}
+
+impl Value {
+    fn option(value: Option<Value>) -> Value {
+        value.unwrap_or(Value::Null)
+    }
+
+    fn number(number: impl Into<u64>) -> Value {
+        Value::Number(number.into().to_string())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => f.write_str(n),
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) => {
+                f.write_char('[')?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(members) => {
+                f.write_char('{')?;
+                for (index, (name, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if u32::from(c) < 0x20 => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// A decoded JSON value and the byte offset it started at, so schema errors
+/// can name the line.
+struct Node<'a> {
+    text: &'a str,
+    at: usize,
+    kind: Kind<'a>,
+}
+
+enum Kind<'a> {
+    Null,
+    Bool(bool),
+    Number(&'a str),
+    String(String),
+    Array(Vec<Node<'a>>),
+    Object(Vec<(String, Node<'a>)>),
+}
+
+impl<'a> Node<'a> {
+    fn invalid(&self) -> io::Error {
+        parse_error(self.text, self.at)
+    }
+
+    fn field(&self, name: &str) -> io::Result<&Node<'a>> {
+        match &self.kind {
+            Kind::Object(members) => members
+                .iter()
+                .find(|(member, _)| member == name)
+                .map(|(_, node)| node)
+                .ok_or_else(|| self.invalid()),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// `None` for `null`, the decoded value otherwise.
+    fn optional<T>(
+        &self,
+        decode: impl FnOnce(&Node<'a>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        match self.kind {
+            Kind::Null => Ok(None),
+            _ => decode(self).map(Some),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&self) -> io::Result<T> {
+        match self.kind {
+            Kind::Number(n) => n.parse().map_err(|_| self.invalid()),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn boolean(&self) -> io::Result<bool> {
+        match self.kind {
+            Kind::Bool(b) => Ok(b),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn string(&self) -> io::Result<&str> {
+        match &self.kind {
+            Kind::String(s) => Ok(s),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn parsed<T: std::str::FromStr>(&self) -> io::Result<T> {
+        self.string()?.parse().map_err(|_| self.invalid())
+    }
+
+    fn array(&self) -> io::Result<&[Node<'a>]> {
+        match &self.kind {
+            Kind::Array(items) => Ok(items),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn sockets(&self) -> io::Result<Vec<Socket>> {
+        self.array()?.iter().map(Node::socket).collect()
+    }
+
+    fn socket(&self) -> io::Result<Socket> {
+        let protocol = self.field("protocol")?;
+        let protocol = match protocol.string()? {
+            "tcp" => Protocol::Tcp,
+            "udp" => Protocol::Udp,
+            _ => return Err(protocol.invalid()),
+        };
+        let family = self.field("family")?;
+        let family = match family.string()? {
+            "v4" => Family::V4,
+            "v6" => Family::V6,
+            _ => return Err(family.invalid()),
+        };
+        let state = self.field("state")?;
+        let state = match state.kind {
+            Kind::Number(_) => SocketState::from_kernel(state.number()?),
+            _ => SocketState::from_ss(state.string()?).ok_or_else(|| state.invalid())?,
+        };
+
+        Ok(Socket {
+            protocol,
+            family,
+            local_addr: self.field("local_addr")?.parsed::<IpAddr>()?,
+            port: self.field("port")?.number()?,
+            remote: self
+                .field("remote")?
+                .optional(|node| node.parsed::<SocketAddr>())?,
+            state,
+            recv_q: self.field("recv_q")?.number()?,
+            send_q: self.field("send_q")?.number()?,
+            inode: self.field("inode")?.optional(|node| node.number())?,
+            uid: self.field("uid")?.optional(|node| node.number())?,
+            v6only: self.field("v6only")?.optional(Node::boolean)?,
+            interface: self
+                .field("interface")?
+                .optional(|node| node.string().map(str::to_string))?,
+        })
+    }
+}
+
+/// How deeply arrays and objects may nest. Documents of the schema nest
+/// three levels deep; the limit keeps hostile input from overflowing the
+/// stack of the recursive decoder.
+const MAX_DEPTH: usize = 32;
+
+/// A recursive-descent JSON parser over the whole text.
+struct Decoder<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(text: &'a str) -> Decoder<'a> {
+        Decoder {
+            text,
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    /// Parses the whole text and checks its schema version.
+    fn document(mut self) -> io::Result<Node<'a>> {
+        let document = self.value()?;
+        self.skip_whitespace();
+        if self.pos < self.text.len() {
+            return Err(self.invalid());
+        }
+
+        let version = document.field("version")?;
+        let number: u32 = version.number()?;
+        if number == 0 {
+            return Err(version.invalid());
+        }
+        if number > JSON_SCHEMA_VERSION {
+            return Err(WalledError::Unsupported {
+                operation: format!("JSON schema version {}", number),
+            }
+            .into());
+        }
+        Ok(document)
+    }
+
+    fn invalid(&self) -> io::Error {
+        parse_error(self.text, self.pos)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> io::Result<()> {
+        if self.peek() != Some(byte) {
+            return Err(self.invalid());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str) -> io::Result<()> {
+        if !self.text[self.pos..].starts_with(word) {
+            return Err(self.invalid());
+        }
+        self.pos += word.len();
+        Ok(())
+    }
+
+    fn value(&mut self) -> io::Result<Node<'a>> {
+        self.skip_whitespace();
+        let at = self.pos;
+        let kind = match self.peek() {
+            Some(b'n') => self.literal("null").map(|_| Kind::Null)?,
+            Some(b't') => self.literal("true").map(|_| Kind::Bool(true))?,
+            Some(b'f') => self.literal("false").map(|_| Kind::Bool(false))?,
+            Some(b'"') => Kind::String(self.string()?),
+            Some(b'[') => Kind::Array(self.nested(Decoder::array)?),
+            Some(b'{') => Kind::Object(self.nested(Decoder::object)?),
+            Some(b'-' | b'0'..=b'9') => Kind::Number(self.number()?),
+            _ => return Err(self.invalid()),
+        };
+        Ok(Node {
+            text: self.text,
+            at,
+            kind,
+        })
+    }
+
+    /// Runs `parse` one nesting level deeper, failing past [`MAX_DEPTH`].
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> io::Result<T>) -> io::Result<T> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.invalid());
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn array(&mut self) -> io::Result<Vec<Node<'a>>> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(items);
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(items);
+                }
+                _ => return Err(self.invalid()),
+            }
+        }
+    }
+
+    fn object(&mut self) -> io::Result<Vec<(String, Node<'a>)>> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(members);
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(members);
+                }
+                _ => return Err(self.invalid()),
+            }
+        }
+    }
+
+    /// The raw text of a number, following the JSON grammar:
+    /// `-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?`. Its value is
+    /// checked when it is converted.
+    fn number(&mut self) -> io::Result<&'a str> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits()?,
+            _ => return Err(self.invalid()),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.digits()?;
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            self.digits()?;
+        }
+        Ok(&self.text[start..self.pos])
+    }
+
+    /// Skips one or more ASCII digits.
+    fn digits(&mut self) -> io::Result<()> {
+        if !matches!(self.peek(), Some(b'0'..=b'9')) {
+            return Err(self.invalid());
+        }
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let Some(c) = rest.chars().next() else {
+                return Err(self.invalid());
+            };
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    out.push(self.escape()?);
+                }
+                c if u32::from(c) < 0x20 => return Err(self.invalid()),
+                c => {
+                    out.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+    }
+
+    fn escape(&mut self) -> io::Result<char> {
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                let high = self.hex4()?;
+                let code = if (0xD800..0xDC00).contains(&high) {
+                    self.literal("\\u")?;
+                    let low = self.hex4()?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(self.invalid());
+                    }
+                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    high
+                };
+                return char::from_u32(code).ok_or_else(|| self.invalid());
+            }
+            _ => return Err(self.invalid()),
+        };
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn hex4(&mut self) -> io::Result<u32> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.invalid())?;
+        self.pos += 4;
+        u32::from_str_radix(digits, 16).map_err(|_| self.invalid())
+    }
+}
+
+/// A [`WalledError::Parse`] for the line holding byte `at` of `text`.
+fn parse_error(text: &str, at: usize) -> io::Error {
+    let at = at.min(text.len());
+    let before = &text[..at];
+    let line_no = before.matches('\n').count() + 1;
+    let start = before.rfind('\n').map_or(0, |i| i + 1);
+    let end = text[at..].find('\n').map_or(text.len(), |i| at + i);
+    WalledError::Parse {
+        line_no,
+        line: text[start..end].to_string(),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_ss_output;
+
+    fn sample() -> Vec<Socket> {
+        let mut sockets = parse_ss_output(
+            "LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n\
+             ESTAB 0 0 10.0.0.5:22 10.0.0.9:51234\n\
+             LISTEN 0 128 [::]:443 [::]:*\n",
+            Protocol::Tcp,
+        );
+        sockets.extend(parse_ss_output(
+            "UNCONN 0 0 127.0.0.53%lo:53 0.0.0.0:*\n",
+            Protocol::Udp,
+        ));
+        sockets[0].inode = Some(4711);
+        sockets[0].uid = Some(0);
+        sockets[1].state = SocketState::Unknown(0x42);
+        sockets
+    }
+
+    #[test]
+    fn sockets_round_trip() {
+        let sockets = sample();
+        let json = sockets_to_json(&sockets);
+        assert!(json.starts_with("{\"version\":1,\"sockets\":[{\"protocol\":\"tcp\""));
+        assert!(json.contains("\"interface\":\"lo\""));
+        assert!(json.contains("\"state\":66"));
+        assert_eq!(sockets_from_json(&json).unwrap(), sockets);
+    }
+
+    #[test]
+    fn port_documents() {
+        let ports: PortSet = [8080, 22, 443].into_iter().collect();
+        assert_eq!(
+            ports_to_json(&ports),
+            "{\"version\":1,\"ports\":[22,443,8080]}"
+        );
+        assert_eq!(
+            ports_to_json(&PortSet::new()),
+            "{\"version\":1,\"ports\":[]}"
+        );
+        assert_eq!(
+            port_status_to_json(8080, false),
+            "{\"version\":1,\"port\":8080,\"free\":false}"
+        );
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        let layout =
+            PortLayout::new(600, 40000..=50000).with_reserved("8000-8100,9090".parse().unwrap());
+        let snapshot = Snapshot::from_sockets(sample()).with_layout(layout);
+
+        let decoded = Snapshot::from_json(&snapshot.to_json()).unwrap();
+        assert_eq!(decoded.sockets(), snapshot.sockets());
+        assert_eq!(decoded.layout(), snapshot.layout());
+        let millis = |s: &Snapshot| {
+            s.captured_at()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis()
+        };
+        assert_eq!(millis(&decoded), millis(&snapshot));
+        assert!(decoded.diff(&snapshot).is_empty());
+    }
+
+    #[test]
+    fn accepts_any_layout_and_unknown_members() {
+        let json = "{\n  \"sockets\": [\n    {\"protocol\": \"udp\", \"family\": \"v6\",\
+                    \"local_addr\": \"::\", \"port\": 5353, \"remote\": null,\
+                    \"state\": \"UNCONN\", \"recv_q\": 0, \"send_q\": 0, \"inode\": null,\
+                    \"uid\": 1000, \"v6only\": false, \"interface\": \"eth\\u0030\",\
+                    \"pid\": 17}\n  ],\n  \"version\": 1\n}\n";
+        let sockets = sockets_from_json(json).unwrap();
+        assert_eq!(sockets.len(), 1);
+        assert_eq!(sockets[0].port, 5353);
+        assert_eq!(sockets[0].v6only, Some(false));
+        assert_eq!(sockets[0].interface.as_deref(), Some("eth0"));
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let mut sockets = sample();
+        sockets[0].interface = Some("we\"ird\\\n\u{1}é😀".to_string());
+        let json = sockets_to_json(&sockets);
+        assert!(json.contains(r#""we\"ird\\\n\u0001é😀""#));
+        assert_eq!(sockets_from_json(&json).unwrap(), sockets);
+        assert_eq!(
+            sockets_from_json(&json.replace("😀", "\\ud83d\\ude00")).unwrap(),
+            sockets
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = sockets_from_json("{\"version\": 1,\n \"sockets\": [\n {\"port\": 22}\n]}")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::Parse { line_no: 3, line }) if line == " {\"port\": 22}"
+        ));
+
+        for bad in [
+            "",
+            "[1,]",
+            "{\"version\": 1} x",
+            "{\"version\": \"1\"}",
+            "\"\\x\"",
+        ] {
+            assert_eq!(
+                sockets_from_json(bad).unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                bad
+            );
+        }
+
+        let newer = sockets_from_json("{\"version\": 2, \"sockets\": []}").unwrap_err();
+        assert_eq!(newer.kind(), io::ErrorKind::Unsupported);
+        let older = sockets_from_json("{\"version\": 0, \"sockets\": []}").unwrap_err();
+        assert_eq!(older.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn numbers_follow_the_json_grammar() {
+        let port = |number: &str| {
+            sockets_from_json(&sockets_to_json(&sample()[..1]).replace("\"port\":22", number))
+        };
+        assert_eq!(port("\"port\":22").unwrap()[0].port, 22);
+        for bad in ["+22", "022", "2+2", "22.", "-", "2e", "1e+"] {
+            let err = port(&format!("\"port\":{}", bad)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", bad);
+        }
+        // Valid JSON numbers that are not ports fail on conversion.
+        for not_a_port in ["-1", "2.5", "1e3", "70000"] {
+            assert!(port(&format!("\"port\":{}", not_a_port)).is_err());
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        let err = sockets_from_json(&deep).unwrap_err();
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::Parse { line_no: 1, .. })
+        ));
+
+        let objects = "{\"a\":".repeat(100_000);
+        assert_eq!(
+            sockets_from_json(&objects).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let fine = format!(
+            "{{\"version\":1,\"sockets\":[],\"extra\":{}{}}}",
+            "[".repeat(MAX_DEPTH - 1),
+            "]".repeat(MAX_DEPTH - 1)
+        );
+        assert!(sockets_from_json(&fine).unwrap().is_empty());
+    }
+}
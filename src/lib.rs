@@ -1,11 +1,139 @@
+mod bind;
+mod cache;
+mod cancel;
+mod diff;
+mod endpoint;
+mod error;
+mod json;
+#[cfg(feature = "netlink")]
+mod netlink;
+mod pending;
+mod portset;
+mod procfs;
+mod query;
+mod ranges;
+mod snapshot;
+mod socket;
+mod source;
+mod ss;
+mod sysctl;
 mod tcp;
+#[cfg(test)]
+mod testing;
 mod udp;
+mod watch;
+
+pub use bind::{
+    free_ports_on,
+    is_free_on,
+};
+
+pub use cache::CachedScanner;
+
+pub use cancel::CancelHandle;
+
+pub use diff::{
+    ProtocolDiff,
+    SnapshotDiff,
+    SocketChange,
+};
+
+pub use endpoint::{
+    SsDialect,
+    SsEndpoint,
+    SsHost,
+    parse_ss_endpoint,
+};
+
+pub use error::WalledError;
+
+pub use json::{
+    JSON_SCHEMA_VERSION,
+    port_status_to_json,
+    ports_to_json,
+    sockets_from_json,
+    sockets_to_json,
+};
+
+pub use pending::PendingScan;
+
+pub use portset::PortSet;
+
+pub use procfs::{
+    ProcNetEntry,
+    parse_proc_net,
+    read_tcp_entries,
+    read_udp_entries,
+};
+
+pub use query::{
+    PortQuery,
+    QueryMode,
+};
+
+pub use ranges::PortRanges;
+
+pub use snapshot::Snapshot;
+
+pub use socket::{
+    Family,
+    Protocol,
+    Socket,
+    SocketState,
+};
+
+pub use source::{
+    ProcfsSource,
+    SocketSource,
+    SourceAnswer,
+    SourceChain,
+    SsSource,
+};
+
+#[cfg(feature = "netlink")]
+pub use source::NetlinkSource;
+
+pub use ss::{
+    CommandRunner,
+    SkippedLine,
+    SsOutput,
+    SystemRunner,
+    parse_ss_lines,
+    parse_ss_output,
+    parse_ss_output_strict,
+};
+
+#[cfg(feature = "netlink")]
+pub use netlink::{
+    diag_tcp_entries,
+    diag_udp_entries,
+};
+
+pub use sysctl::{
+    DEFAULT_LOCAL_PORT_RANGE,
+    IANA_UNPRIVILEGED_PORT_START,
+    PortClass,
+    PortLayout,
+    Sysctls,
+    local_port_range,
+    reserved_ports,
+    unprivileged_port_start,
+};
 
 pub use tcp::{
     privileged_tcp_used,
     privileged_tcp_free,
     unprivileged_tcp_used,
     unprivileged_tcp_free,
+    unprivileged_tcp_free_non_ephemeral,
+    unprivileged_tcp_free_unreserved,
+    privileged_tcp_used_iana,
+    privileged_tcp_free_iana,
+    unprivileged_tcp_used_iana,
+    unprivileged_tcp_free_iana,
+    tcp_used_set,
+    tcp_free_set,
+    tcp_sockets,
 };
 
 pub use udp::{
@@ -13,4 +141,18 @@ pub use udp::{
     privileged_udp_free,
     unprivileged_udp_used,
     unprivileged_udp_free,
+    unprivileged_udp_free_non_ephemeral,
+    unprivileged_udp_free_unreserved,
+    privileged_udp_used_iana,
+    privileged_udp_free_iana,
+    unprivileged_udp_used_iana,
+    unprivileged_udp_free_iana,
+    udp_used_set,
+    udp_free_set,
+    udp_sockets,
+};
+
+pub use watch::{
+    PortEvent,
+    Watcher,
 };
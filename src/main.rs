@@ -0,0 +1,462 @@
+use std::env;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::process::ExitCode;
+
+use walled::{
+    PortLayout, PortQuery, PortRanges, PortSet, Protocol, QueryMode, Snapshot, Socket, SourceChain,
+    port_status_to_json, ports_to_json, sockets_to_json,
+};
+
+const USAGE: &str = "\
+Usage: walled <COMMAND> [OPTIONS]
+
+Commands:
+  used           List the ports something listens on
+  free           List the ports nothing listens on
+  check <PORT>   Tell whether PORT is free
+  who <PORT>     Show the sockets listening on PORT
+
+Options:
+  --tcp                  Only look at TCP sockets
+  --udp                  Only look at UDP sockets (default: TCP and UDP)
+  --range <RANGES>       Ports to list, e.g. 1-1023,8080 or `privileged` /
+                         `unprivileged` (used and free; default: 1-65535)
+  --addr <IP>            Only count sockets that block binding IP
+  --format <FORMAT>      table, json or ranges (default: table)
+  -h, --help             Print this help
+  -V, --version          Print the version
+
+Output:
+  used    table: one socket per line; json: socket document; ranges: 22,80-81
+  free    table: one port per line; json: {\"version\":V,\"ports\":[...]}; ranges
+  check   table: a sentence; json: {\"version\":V,\"port\":P,\"free\":B}
+  who     table or json, as for used
+
+Exit status:
+  0  success; for check, the port is free
+  1  check only: the port is used
+  2  error, e.g. a bad argument or no backend could be queried
+";
+
+/// What the command line asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Used,
+    Free,
+    Check(u16),
+    Who(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Table,
+    Json,
+    Ranges,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Range {
+    Ports(PortRanges),
+    Privileged,
+    Unprivileged,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Args {
+    command: Command,
+    protocols: Vec<Protocol>,
+    range: Option<Range>,
+    addr: Option<IpAddr>,
+    format: Format,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Parsed {
+    Run(Args),
+    Help,
+    Version,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Parsed::Run(args)) => args,
+        Ok(Parsed::Help) => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(Parsed::Version) => {
+            println!("walled {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("walled: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args, &mut io::stdout().lock()) {
+        Ok(code) => code,
+        // The reader went away, e.g. `walled free | head`.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("walled: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Parsed, String> {
+    let mut args = args.into_iter();
+    let mut command = None;
+    let mut protocols = Vec::new();
+    let mut range = None;
+    let mut addr = None;
+    let mut format = Format::Table;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Parsed::Help),
+            "-V" | "--version" => return Ok(Parsed::Version),
+            "--tcp" => protocols.push(Protocol::Tcp),
+            "--udp" => protocols.push(Protocol::Udp),
+            "--range" => range = Some(parse_range(&value("--range")?)?),
+            "--addr" => {
+                let text = value("--addr")?;
+                addr = Some(
+                    text.parse()
+                        .map_err(|_| format!("invalid address `{}`", text))?,
+                );
+            }
+            "--format" => {
+                format = match value("--format")?.as_str() {
+                    "table" => Format::Table,
+                    "json" => Format::Json,
+                    "ranges" => Format::Ranges,
+                    other => return Err(format!("unknown format `{}`", other)),
+                }
+            }
+            option if option.starts_with('-') => {
+                return Err(format!("unknown option `{}`", option));
+            }
+            word if command.is_none() => {
+                command = Some(match word {
+                    "used" => Command::Used,
+                    "free" => Command::Free,
+                    "check" => Command::Check(parse_port(value("check")?)?),
+                    "who" => Command::Who(parse_port(value("who")?)?),
+                    other => return Err(format!("unknown command `{}`", other)),
+                });
+            }
+            extra => return Err(format!("unexpected argument `{}`", extra)),
+        }
+    }
+
+    let command = command.ok_or("no command given")?;
+    if range.is_some() && matches!(command, Command::Check(_) | Command::Who(_)) {
+        return Err("--range only applies to used and free".to_string());
+    }
+    if format == Format::Ranges && matches!(command, Command::Check(_) | Command::Who(_)) {
+        return Err("--format ranges only applies to used and free".to_string());
+    }
+    if protocols.is_empty() {
+        protocols = vec![Protocol::Tcp, Protocol::Udp];
+    }
+    protocols.sort_unstable();
+    protocols.dedup();
+
+    Ok(Parsed::Run(Args {
+        command,
+        protocols,
+        range,
+        addr,
+        format,
+    }))
+}
+
+fn parse_port(text: String) -> Result<u16, String> {
+    text.parse().map_err(|_| format!("invalid port `{}`", text))
+}
+
+fn parse_range(text: &str) -> Result<Range, String> {
+    match text {
+        "privileged" => Ok(Range::Privileged),
+        "unprivileged" => Ok(Range::Unprivileged),
+        _ => match text.parse::<PortRanges>() {
+            Ok(ranges) if !ranges.is_empty() => Ok(Range::Ports(ranges)),
+            _ => Err(format!("invalid port range `{}`", text)),
+        },
+    }
+}
+
+fn run(args: &Args, out: &mut impl Write) -> io::Result<ExitCode> {
+    let chain = SourceChain::default();
+    let mut sockets = Vec::new();
+    for protocol in &args.protocols {
+        sockets.extend(chain.listening_sockets(*protocol)?.sockets);
+    }
+    let layout = match args.range {
+        Some(Range::Privileged | Range::Unprivileged) => PortLayout::current()?,
+        _ => PortLayout::default(),
+    };
+    let snapshot = Snapshot::from_sockets(sockets).with_layout(layout);
+
+    report(args, &snapshot, out)
+}
+
+/// Answers the command from `snapshot` and writes the result to `out`.
+fn report(args: &Args, snapshot: &Snapshot, out: &mut impl Write) -> io::Result<ExitCode> {
+    match args.command {
+        Command::Used => {
+            let ports = select(args, snapshot, QueryMode::Used);
+            match args.format {
+                Format::Table => write_sockets(out, &blocking(args, snapshot, &ports))?,
+                Format::Json => writeln!(
+                    out,
+                    "{}",
+                    sockets_to_json(&blocking(args, snapshot, &ports))
+                )?,
+                Format::Ranges => writeln!(out, "{}", PortRanges::from(&ports))?,
+            }
+        }
+        Command::Free => {
+            let ports = select(args, snapshot, QueryMode::Free);
+            match args.format {
+                Format::Table => {
+                    for port in ports.iter() {
+                        writeln!(out, "{}", port)?;
+                    }
+                }
+                Format::Json => writeln!(out, "{}", ports_to_json(&ports))?,
+                Format::Ranges => writeln!(out, "{}", PortRanges::from(&ports))?,
+            }
+        }
+        Command::Check(port) => {
+            let free = blocking(args, snapshot, &PortSet::from_range(port..=port)).is_empty();
+            match args.format {
+                Format::Json => writeln!(out, "{}", port_status_to_json(port, free))?,
+                _ => writeln!(
+                    out,
+                    "port {} is {}",
+                    port,
+                    if free { "free" } else { "used" }
+                )?,
+            }
+            if !free {
+                return Ok(ExitCode::from(1));
+            }
+        }
+        Command::Who(port) => {
+            let sockets = blocking(args, snapshot, &PortSet::from_range(port..=port));
+            match args.format {
+                Format::Json => writeln!(out, "{}", sockets_to_json(&sockets))?,
+                _ => write_sockets(out, &sockets)?,
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs the `--tcp/--udp`, `--range` and `--addr` part of the command line
+/// as a [`PortQuery`] against `snapshot`.
+fn select(args: &Args, snapshot: &Snapshot, mode: QueryMode) -> PortSet {
+    let mut query = PortQuery::new()
+        .protocols(args.protocols.iter().copied())
+        .mode(mode);
+    if let Some(addr) = args.addr {
+        query = query.bind_addr(addr);
+    }
+
+    match &args.range {
+        None => query.evaluate(snapshot),
+        Some(Range::Privileged) => query.privileged().evaluate(snapshot),
+        Some(Range::Unprivileged) => query.unprivileged().evaluate(snapshot),
+        Some(Range::Ports(ranges)) => {
+            let wanted = PortSet::from(ranges);
+            &query.evaluate(snapshot) & &wanted
+        }
+    }
+}
+
+/// The sockets on `ports` that match the protocols and `--addr`.
+fn blocking(args: &Args, snapshot: &Snapshot, ports: &PortSet) -> Vec<Socket> {
+    let mut sockets: Vec<Socket> = snapshot
+        .sockets()
+        .iter()
+        .filter(|socket| args.protocols.contains(&socket.protocol))
+        .filter(|socket| ports.contains(socket.port))
+        .filter(|socket| args.addr.is_none_or(|addr| socket.conflicts_with(addr)))
+        .cloned()
+        .collect();
+    sockets.sort_by_key(|socket| (socket.port, socket.protocol, socket.local_addr));
+    sockets
+}
+
+fn write_sockets(out: &mut impl Write, sockets: &[Socket]) -> io::Result<()> {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+    writeln!(
+        out,
+        "{:<5} {:<47} {:>6} {:>10}",
+        "PROTO", "LOCAL", "UID", "INODE"
+    )?;
+    for socket in sockets {
+        let mut local = socket.local().to_string();
+        if let Some(interface) = &socket.interface {
+            local = format!("{}%{}", local, interface);
+        }
+        writeln!(
+            out,
+            "{:<5} {:<47} {:>6} {:>10}",
+            socket.protocol.to_string(),
+            local,
+            optional(socket.uid.map(|uid| uid.to_string())),
+            optional(socket.inode.map(|inode| inode.to_string())),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use walled::parse_ss_output;
+
+    fn parse(line: &str) -> Result<Parsed, String> {
+        parse_args(line.split_whitespace().map(str::to_string))
+    }
+
+    fn args(line: &str) -> Args {
+        match parse(line) {
+            Ok(Parsed::Run(args)) => args,
+            other => panic!("unexpected parse {:?}", other),
+        }
+    }
+
+    fn sample() -> Snapshot {
+        let mut sockets = parse_ss_output(
+            "LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n\
+             LISTEN 0 128 127.0.0.1:8080 0.0.0.0:*\n\
+             LISTEN 0 128 [::]:443 [::]:*\n",
+            Protocol::Tcp,
+        );
+        sockets.extend(parse_ss_output(
+            "UNCONN 0 0 0.0.0.0:53 0.0.0.0:*\n",
+            Protocol::Udp,
+        ));
+        Snapshot::from_sockets(sockets)
+    }
+
+    fn output(line: &str) -> (String, ExitCode) {
+        let mut out = Vec::new();
+        let code = report(&args(line), &sample(), &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), code)
+    }
+
+    #[test]
+    fn parses_commands_and_options() {
+        let parsed = args("free --udp --range 1-1023,8080 --addr 10.0.0.5 --format ranges");
+        assert_eq!(parsed.command, Command::Free);
+        assert_eq!(parsed.protocols, vec![Protocol::Udp]);
+        assert_eq!(
+            parsed.range,
+            Some(Range::Ports("1-1023,8080".parse().unwrap()))
+        );
+        assert_eq!(parsed.addr, Some(IpAddr::from([10, 0, 0, 5])));
+        assert_eq!(parsed.format, Format::Ranges);
+
+        let parsed = args("check 8080");
+        assert_eq!(parsed.command, Command::Check(8080));
+        assert_eq!(parsed.protocols, vec![Protocol::Tcp, Protocol::Udp]);
+        assert_eq!(
+            args("used --range privileged").range,
+            Some(Range::Privileged)
+        );
+
+        assert_eq!(parse("used --help"), Ok(Parsed::Help));
+        assert_eq!(parse("-V"), Ok(Parsed::Version));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        for bad in [
+            "",
+            "listen",
+            "used --range",
+            "used --range 9-1",
+            "used --format yaml",
+            "used --verbose",
+            "check",
+            "check http",
+            "who 70000",
+            "used free",
+            "check 80 --range 1-100",
+            "who 80 --format ranges",
+            "free --addr localhost",
+        ] {
+            assert!(parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn used_and_free() {
+        let (out, code) = output("used --format ranges");
+        assert_eq!(out, "22,53,443,8080\n");
+        assert_eq!(code, ExitCode::SUCCESS);
+
+        assert_eq!(
+            output("used --tcp --range 1-1023 --format ranges").0,
+            "22,443\n"
+        );
+        assert_eq!(
+            output("free --range 20-25 --format ranges").0,
+            "20-21,23-25\n"
+        );
+        assert_eq!(output("free --range 52-54 --tcp").0, "52\n53\n54\n");
+        assert_eq!(
+            output("free --range 8079-8081 --addr 10.0.0.5 --format json").0,
+            "{\"version\":1,\"ports\":[8079,8080,8081]}\n"
+        );
+
+        let (table, _) = output("used --tcp --range 22");
+        assert!(table.starts_with("PROTO"));
+        assert!(
+            table
+                .lines()
+                .nth(1)
+                .unwrap()
+                .starts_with("tcp   0.0.0.0:22 ")
+        );
+        assert!(
+            output("used --format json")
+                .0
+                .starts_with("{\"version\":1,\"sockets\":[")
+        );
+    }
+
+    #[test]
+    fn check_exit_codes() {
+        let (out, code) = output("check 8080");
+        assert_eq!(out, "port 8080 is used\n");
+        assert_eq!(code, ExitCode::from(1));
+
+        let (out, code) = output("check 8080 --addr 10.0.0.5 --format json");
+        assert_eq!(out, "{\"version\":1,\"port\":8080,\"free\":true}\n");
+        assert_eq!(code, ExitCode::SUCCESS);
+
+        assert_eq!(output("check 53 --tcp").1, ExitCode::SUCCESS);
+        assert_eq!(output("check 53 --udp").1, ExitCode::from(1));
+    }
+
+    #[test]
+    fn who_lists_the_sockets() {
+        let (out, code) = output("who 443");
+        assert_eq!(out.lines().count(), 2);
+        assert!(out.contains("[::]:443"));
+        assert_eq!(code, ExitCode::SUCCESS);
+        assert_eq!(output("who 9999").0.lines().count(), 1);
+    }
+}
@@ -0,0 +1,466 @@
+use std::ffi::{CStr, c_char, c_void};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use crate::error::WalledError;
+use crate::procfs::{ProcNetEntry, TCP_LISTEN, UDP_UNCONN};
+use crate::socket::{Protocol, Socket};
+
+const AF_NETLINK: i32 = 16;
+const AF_INET: u8 = 2;
+const AF_INET6: u8 = 10;
+const SOCK_RAW: i32 = 3;
+const SOCK_CLOEXEC: i32 = 0o2000000;
+const NETLINK_SOCK_DIAG: i32 = 4;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x001;
+const NLM_F_DUMP: u16 = 0x300;
+
+const INET_DIAG_REQ_BYTECODE: u16 = 1;
+const INET_DIAG_SKV6ONLY: u16 = 11;
+const INET_DIAG_BC_S_GE: u8 = 2;
+const INET_DIAG_BC_S_LE: u8 = 3;
+
+const NLMSG_HDRLEN: usize = 16;
+
+const ENOENT: i32 = 2;
+const EPERM: i32 = 1;
+const EACCES: i32 = 13;
+const EPROTONOSUPPORT: i32 = 93;
+const EAFNOSUPPORT: i32 = 97;
+const INET_DIAG_SOCKID_LEN: usize = 48;
+const INET_DIAG_REQ_V2_LEN: usize = 8 + INET_DIAG_SOCKID_LEN;
+const INET_DIAG_MSG_LEN: usize = 4 + INET_DIAG_SOCKID_LEN + 20;
+
+unsafe extern "C" {
+    fn socket(domain: i32, ty: i32, protocol: i32) -> i32;
+    fn sendto(
+        fd: RawFd,
+        buf: *const c_void,
+        len: usize,
+        flags: i32,
+        addr: *const c_void,
+        addrlen: u32,
+    ) -> isize;
+    fn recv(fd: RawFd, buf: *mut c_void, len: usize, flags: i32) -> isize;
+    fn if_indextoname(ifindex: u32, ifname: *mut c_char) -> *mut c_char;
+}
+
+/// Listening TCP sockets whose local port lies in `ports`, IPv4 and IPv6
+/// combined, fetched from the kernel over `NETLINK_SOCK_DIAG`.
+///
+/// Both the state and the port range are filtered inside the kernel, so only
+/// matching sockets cross the netlink socket. The entries carry the same
+/// fields as [`read_tcp_entries`](crate::read_tcp_entries).
+pub fn diag_tcp_entries(ports: RangeInclusive<u16>) -> io::Result<Vec<ProcNetEntry>> {
+    Ok(dump_both(IPPROTO_TCP, 1 << TCP_LISTEN, &ports)?
+        .into_iter()
+        .map(|diag| diag.entry)
+        .collect())
+}
+
+/// Unconnected UDP sockets whose local port lies in `ports`, IPv4 and IPv6
+/// combined, fetched from the kernel over `NETLINK_SOCK_DIAG`.
+///
+/// This is the set `ss -uln` lists. `drops` is not part of the diag reply and
+/// is always `None`; use [`read_udp_entries`](crate::read_udp_entries) for it.
+pub fn diag_udp_entries(ports: RangeInclusive<u16>) -> io::Result<Vec<ProcNetEntry>> {
+    Ok(dump_both(IPPROTO_UDP, 1 << UDP_UNCONN, &ports)?
+        .into_iter()
+        .map(|diag| diag.entry)
+        .collect())
+}
+
+/// Every listening socket for `protocol`, as reported by `NETLINK_SOCK_DIAG`.
+///
+/// Unlike procfs, the reply tells whether IPv6 sockets are `IPV6_V6ONLY`.
+pub(crate) fn listening_sockets(protocol: Protocol) -> io::Result<Vec<Socket>> {
+    let (ipproto, states) = match protocol {
+        Protocol::Tcp => (IPPROTO_TCP, 1 << TCP_LISTEN),
+        Protocol::Udp => (IPPROTO_UDP, 1 << UDP_UNCONN),
+    };
+    Ok(dump_both(ipproto, states, &(0..=65535))?
+        .into_iter()
+        .map(|diag| Socket {
+            v6only: diag.v6only,
+            interface: interface_name(diag.ifindex),
+            ..diag.entry.into_socket(protocol)
+        })
+        .collect())
+}
+
+/// A decoded `inet_diag_msg` plus the attributes this crate cares about.
+struct DiagEntry {
+    entry: ProcNetEntry,
+    /// Value of the `INET_DIAG_SKV6ONLY` attribute (IPv6 sockets only).
+    v6only: Option<bool>,
+    /// `idiag_if`: index of the device the socket is bound to, or 0.
+    ifindex: u32,
+}
+
+/// Resolves a bound-device index to its name, e.g. `2` to `eth0`.
+fn interface_name(ifindex: u32) -> Option<String> {
+    if ifindex == 0 {
+        return None;
+    }
+    let mut name = [0 as c_char; 16];
+    // SAFETY: `name` has room for IF_NAMESIZE bytes, as the call requires.
+    let ptr = unsafe { if_indextoname(ifindex, name.as_mut_ptr()) };
+    if ptr.is_null() {
+        return None;
+    }
+    // SAFETY: on success the kernel wrote a NUL-terminated name into `name`.
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+fn dump_both(protocol: u8, states: u32, ports: &RangeInclusive<u16>) -> io::Result<Vec<DiagEntry>> {
+    let fd = open_socket()?;
+    let mut entries = dump(&fd, AF_INET, protocol, states, ports)?;
+
+    match dump(&fd, AF_INET6, protocol, states, ports) {
+        Ok(v6) => entries.extend(v6),
+        // IPv6 disabled at boot: there is simply nothing to report.
+        Err(e) if e.raw_os_error() == Some(EAFNOSUPPORT) => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(entries)
+}
+
+fn open_socket() -> io::Result<OwnedFd> {
+    // SAFETY: plain syscall with constant arguments; the returned descriptor
+    // is checked before ownership is taken.
+    let fd = unsafe { socket(AF_NETLINK, SOCK_RAW | SOCK_CLOEXEC, NETLINK_SOCK_DIAG) };
+    if fd < 0 {
+        let err = io::Error::last_os_error();
+        return Err(match err.raw_os_error() {
+            Some(EPROTONOSUPPORT | EAFNOSUPPORT) => WalledError::Unsupported {
+                operation: "NETLINK_SOCK_DIAG sockets".to_string(),
+            }
+            .into(),
+            Some(EPERM | EACCES) => WalledError::PermissionDenied {
+                operation: "open a NETLINK_SOCK_DIAG socket".to_string(),
+            }
+            .into(),
+            _ => err,
+        });
+    }
+    // SAFETY: `fd` is a freshly created, valid descriptor owned by nobody else.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn dump(
+    fd: &OwnedFd,
+    family: u8,
+    protocol: u8,
+    states: u32,
+    ports: &RangeInclusive<u16>,
+) -> io::Result<Vec<DiagEntry>> {
+    let request = encode_request(family, protocol, states, ports);
+
+    // struct sockaddr_nl addressed to the kernel (pid 0, no groups).
+    let mut kernel = [0u8; 12];
+    kernel[..2].copy_from_slice(&(AF_NETLINK as u16).to_ne_bytes());
+
+    // SAFETY: both buffers are valid for the lengths passed alongside them.
+    let sent = unsafe {
+        sendto(
+            fd.as_raw_fd(),
+            request.as_ptr().cast(),
+            request.len(),
+            0,
+            kernel.as_ptr().cast(),
+            kernel.len() as u32,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut entries = Vec::new();
+    let mut buf = vec![0u8; 32 * 1024];
+
+    loop {
+        // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+        let n = unsafe { recv(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if parse_messages(&buf[..n as usize], &mut entries)? {
+            return Ok(entries);
+        }
+    }
+}
+
+/// Builds an `nlmsghdr` + `inet_diag_req_v2` dump request, followed by a
+/// bytecode filter restricting the local port to `ports`.
+fn encode_request(family: u8, protocol: u8, states: u32, ports: &RangeInclusive<u16>) -> Vec<u8> {
+    let bytecode = port_range_bytecode(ports);
+    let attr_len = 4 + bytecode.len();
+    let total = NLMSG_HDRLEN + INET_DIAG_REQ_V2_LEN + attr_len;
+
+    let mut msg = Vec::with_capacity(total);
+    msg.extend_from_slice(&(total as u32).to_ne_bytes());
+    msg.extend_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+    msg.extend_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+    msg.extend_from_slice(&1u32.to_ne_bytes()); // sequence number
+    msg.extend_from_slice(&0u32.to_ne_bytes()); // port id, filled in by the kernel
+
+    msg.extend_from_slice(&[family, protocol, 0, 0]);
+    msg.extend_from_slice(&states.to_ne_bytes());
+    msg.extend_from_slice(&[0u8; INET_DIAG_SOCKID_LEN]);
+
+    msg.extend_from_slice(&(attr_len as u16).to_ne_bytes());
+    msg.extend_from_slice(&INET_DIAG_REQ_BYTECODE.to_ne_bytes());
+    msg.extend_from_slice(&bytecode);
+
+    msg
+}
+
+/// Encodes `sport >= start && sport <= end` as `inet_diag` bytecode.
+///
+/// Each comparison is an `inet_diag_bc_op` followed by a second op whose `no`
+/// field holds the port. A jump that lands exactly on the end of the program
+/// accepts the socket; jumping four bytes past it rejects the socket.
+fn port_range_bytecode(ports: &RangeInclusive<u16>) -> Vec<u8> {
+    let mut bc = Vec::with_capacity(16);
+    for (code, port, remaining) in [
+        (INET_DIAG_BC_S_GE, *ports.start(), 16u16),
+        (INET_DIAG_BC_S_LE, *ports.end(), 8u16),
+    ] {
+        bc.extend_from_slice(&[code, 8]);
+        bc.extend_from_slice(&(remaining + 4).to_ne_bytes());
+        bc.extend_from_slice(&[0, 0]);
+        bc.extend_from_slice(&port.to_ne_bytes());
+    }
+    bc
+}
+
+/// Appends every `inet_diag_msg` in `buf` to `entries`.
+///
+/// Returns `Ok(true)` once `NLMSG_DONE` has been seen.
+fn parse_messages(mut buf: &[u8], entries: &mut Vec<DiagEntry>) -> io::Result<bool> {
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+        let kind = u16::from_ne_bytes(buf[4..6].try_into().unwrap());
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            return Err(truncated());
+        }
+        let payload = &buf[NLMSG_HDRLEN..len];
+
+        match kind {
+            NLMSG_DONE => return Ok(true),
+            NLMSG_ERROR => {
+                let errno = i32::from_ne_bytes(
+                    payload.get(0..4).ok_or_else(truncated)?.try_into().unwrap(),
+                );
+                // The kernel answers ENOENT when no diag handler is loaded for
+                // the protocol (e.g. `udp_diag` missing).
+                return Err(match -errno {
+                    ENOENT => WalledError::Unsupported {
+                        operation: "sock_diag for this protocol".to_string(),
+                    }
+                    .into(),
+                    errno => io::Error::from_raw_os_error(errno),
+                });
+            }
+            SOCK_DIAG_BY_FAMILY => entries.push(parse_diag_msg(payload).ok_or_else(truncated)?),
+            _ => {}
+        }
+
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+    Ok(false)
+}
+
+/// Decodes one `struct inet_diag_msg` and its trailing attributes.
+fn parse_diag_msg(msg: &[u8]) -> Option<DiagEntry> {
+    if msg.len() < INET_DIAG_MSG_LEN {
+        return None;
+    }
+
+    let family = msg[0];
+    let state = msg[1];
+    let id = &msg[4..4 + INET_DIAG_SOCKID_LEN];
+    let tail = &msg[4 + INET_DIAG_SOCKID_LEN..];
+    let word = |i: usize| u32::from_ne_bytes(tail[i * 4..i * 4 + 4].try_into().unwrap());
+
+    let entry = ProcNetEntry {
+        local_addr: decode_addr(family, &id[4..20])?,
+        local_port: u16::from_be_bytes([id[0], id[1]]),
+        remote_addr: decode_addr(family, &id[20..36])?,
+        remote_port: u16::from_be_bytes([id[2], id[3]]),
+        state,
+        rx_queue: word(1),
+        tx_queue: word(2),
+        uid: word(3),
+        inode: u64::from(word(4)),
+        drops: None,
+    };
+
+    let mut v6only = None;
+    let mut attrs = &msg[INET_DIAG_MSG_LEN..];
+    while attrs.len() >= 4 {
+        let len = usize::from(u16::from_ne_bytes([attrs[0], attrs[1]]));
+        let kind = u16::from_ne_bytes([attrs[2], attrs[3]]);
+        if len < 4 || len > attrs.len() {
+            break;
+        }
+        if kind == INET_DIAG_SKV6ONLY && len > 4 {
+            v6only = Some(attrs[4] != 0);
+        }
+        attrs = &attrs[((len + 3) & !3).min(attrs.len())..];
+    }
+
+    Some(DiagEntry {
+        entry,
+        v6only,
+        ifindex: u32::from_ne_bytes(id[36..40].try_into().unwrap()),
+    })
+}
+
+/// Addresses in `inet_diag_sockid` are stored in network byte order.
+fn decode_addr(family: u8, raw: &[u8]) -> Option<IpAddr> {
+    match family {
+        AF_INET => Some(IpAddr::V4(Ipv4Addr::new(raw[0], raw[1], raw[2], raw[3]))),
+        AF_INET6 => {
+            let octets: [u8; 16] = raw.try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "truncated sock_diag message")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diag_msg(
+        family: u8,
+        state: u8,
+        port: u16,
+        addr: &[u8],
+        inode: u32,
+        v6only: Option<u8>,
+    ) -> Vec<u8> {
+        let mut payload = vec![family, state, 0, 0];
+        payload.extend_from_slice(&port.to_be_bytes());
+        payload.extend_from_slice(&0u16.to_be_bytes());
+        let mut src = [0u8; 16];
+        src[..addr.len()].copy_from_slice(addr);
+        payload.extend_from_slice(&src);
+        payload.extend_from_slice(&[0u8; 16]); // dst
+        payload.extend_from_slice(&[0u8; 12]); // if + cookie
+        for word in [0u32, 5, 7, 1000, inode] {
+            payload.extend_from_slice(&word.to_ne_bytes());
+        }
+        if let Some(flag) = v6only {
+            payload.extend_from_slice(&5u16.to_ne_bytes());
+            payload.extend_from_slice(&INET_DIAG_SKV6ONLY.to_ne_bytes());
+            payload.extend_from_slice(&[flag, 0, 0, 0]);
+        }
+
+        let mut msg = ((NLMSG_HDRLEN + payload.len()) as u32)
+            .to_ne_bytes()
+            .to_vec();
+        msg.extend_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+        msg.extend_from_slice(&[0u8; 10]);
+        msg.extend_from_slice(&payload);
+        msg
+    }
+
+    fn done() -> Vec<u8> {
+        let mut msg = (NLMSG_HDRLEN as u32 + 4).to_ne_bytes().to_vec();
+        msg.extend_from_slice(&NLMSG_DONE.to_ne_bytes());
+        msg.extend_from_slice(&[0u8; 14]);
+        msg
+    }
+
+    #[test]
+    fn bytecode_accepts_range_and_rejects_outside() {
+        let bc = port_range_bytecode(&(1024..=2048));
+        assert_eq!(bc.len(), 16);
+        assert_eq!(bc[0], INET_DIAG_BC_S_GE);
+        assert_eq!(u16::from_ne_bytes([bc[2], bc[3]]), 20);
+        assert_eq!(u16::from_ne_bytes([bc[6], bc[7]]), 1024);
+        assert_eq!(bc[8], INET_DIAG_BC_S_LE);
+        assert_eq!(u16::from_ne_bytes([bc[10], bc[11]]), 12);
+        assert_eq!(u16::from_ne_bytes([bc[14], bc[15]]), 2048);
+    }
+
+    #[test]
+    fn request_length_matches_header() {
+        let req = encode_request(AF_INET, IPPROTO_TCP, 1 << TCP_LISTEN, &(0..=65535));
+        assert_eq!(
+            u32::from_ne_bytes(req[0..4].try_into().unwrap()) as usize,
+            req.len()
+        );
+        assert_eq!(req.len(), NLMSG_HDRLEN + INET_DIAG_REQ_V2_LEN + 4 + 16);
+    }
+
+    #[test]
+    fn parses_diag_messages_until_done() {
+        let mut buf = diag_msg(AF_INET, TCP_LISTEN, 22, &[127, 0, 0, 1], 42, None);
+        buf.extend(diag_msg(AF_INET6, TCP_LISTEN, 443, &[0; 16], 43, Some(1)));
+        buf.extend(done());
+
+        let mut entries = Vec::new();
+        assert!(parse_messages(&buf, &mut entries).unwrap());
+        assert_eq!(entries.len(), 2);
+        let (v4, v6) = (&entries[0].entry, &entries[1].entry);
+        assert_eq!(v4.local_addr, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(v4.local_port, 22);
+        assert_eq!(v4.rx_queue, 5);
+        assert_eq!(v4.tx_queue, 7);
+        assert_eq!(v4.uid, 1000);
+        assert_eq!(v4.inode, 42);
+        assert_eq!(entries[0].v6only, None);
+        assert_eq!(v6.local_addr, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        assert_eq!(v6.local_port, 443);
+        assert_eq!(entries[1].v6only, Some(true));
+    }
+
+    fn error(errno: i32) -> Vec<u8> {
+        let mut msg = (NLMSG_HDRLEN as u32 + 4).to_ne_bytes().to_vec();
+        msg.extend_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        msg.extend_from_slice(&[0u8; 10]);
+        msg.extend_from_slice(&(-errno).to_ne_bytes());
+        msg
+    }
+
+    #[test]
+    fn reports_errors_and_partial_dumps() {
+        // A dump split over several reads: no NLMSG_DONE yet.
+        let mut entries = Vec::new();
+        let first = diag_msg(AF_INET, TCP_LISTEN, 22, &[0; 4], 42, None);
+        assert!(!parse_messages(&first, &mut entries).unwrap());
+        assert_eq!(entries.len(), 1);
+
+        let err = parse_messages(&first[..first.len() - 1], &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = parse_messages(&error(ENOENT), &mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::Unsupported { .. })
+        ));
+        let err = parse_messages(&error(EACCES), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EACCES));
+    }
+}
@@ -0,0 +1,238 @@
+use std::future::Future;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/// A scan running on a helper thread.
+///
+/// Blocking backends (`ss`, procfs reads) would otherwise tie up the thread
+/// that asks. A [`PendingScan`] starts the scan on its own thread and hands
+/// the answer back in whichever way suits the caller:
+///
+///   * [`PendingScan::try_poll`] – checks without blocking, for event loops.
+///   * [`PendingScan::wait`]     – blocks until the answer is in.
+///   * `.await`                  – it implements [`Future`], woken by the
+///     helper thread, so it works under tokio, async-std, smol or any other
+///     executor without pulling in a runtime.
+///
+/// ```no_run
+/// use walled::PortQuery;
+///
+/// # async fn example() -> std::io::Result<()> {
+/// let used = PortQuery::tcp().privileged().spawn().await?;
+/// println!("privileged TCP ports in use: {:?}", used);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Dropping the handle does not stop the scan; give the chain a
+/// [`CancelHandle`](crate::CancelHandle) for that.
+#[derive(Debug)]
+pub struct PendingScan<T> {
+    shared: Arc<Shared<T>>,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    done: Condvar,
+}
+
+#[derive(Debug)]
+enum State<T> {
+    Running(Option<Waker>),
+    Done(io::Result<T>),
+    Taken,
+}
+
+impl<T: Send + 'static> PendingScan<T> {
+    /// Runs `scan` on a new thread.
+    ///
+    /// Any blocking call of this crate works, e.g.
+    /// `PendingScan::spawn(Snapshot::capture)`. If the thread cannot be
+    /// started, or `scan` panics, the scan completes with an error.
+    pub fn spawn(scan: impl FnOnce() -> io::Result<T> + Send + 'static) -> PendingScan<T> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::Running(None)),
+            done: Condvar::new(),
+        });
+
+        let worker = Arc::clone(&shared);
+        let spawned = thread::Builder::new()
+            .name("walled-scan".to_string())
+            .spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(scan))
+                    .unwrap_or_else(|_| Err(io::Error::other("the scan panicked")));
+                worker.finish(result);
+            });
+        if let Err(e) = spawned {
+            shared.finish(Err(e));
+        }
+
+        PendingScan { shared }
+    }
+}
+
+impl<T> PendingScan<T> {
+    /// Whether the answer is in, without taking it.
+    pub fn is_finished(&self) -> bool {
+        !matches!(*self.shared.lock(), State::Running(_))
+    }
+
+    /// The answer if the scan has finished, or `None` while it is running.
+    ///
+    /// # Panics
+    ///
+    /// If the answer was already returned by an earlier call.
+    pub fn try_poll(&mut self) -> Option<io::Result<T>> {
+        let mut state = self.shared.lock();
+        match *state {
+            State::Running(_) => None,
+            _ => Some(take(&mut state)),
+        }
+    }
+
+    /// Blocks until the scan has finished and returns its answer.
+    ///
+    /// # Panics
+    ///
+    /// If the answer was already returned by [`PendingScan::try_poll`].
+    pub fn wait(self) -> io::Result<T> {
+        let mut state = self.shared.lock();
+        while matches!(*state, State::Running(_)) {
+            state = self
+                .shared
+                .done
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        take(&mut state)
+    }
+}
+
+impl<T> Future for PendingScan<T> {
+    type Output = io::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+        let mut state = self.shared.lock();
+        match &mut *state {
+            State::Running(waker) => {
+                // Only the latest waker is kept, as the `Future` contract asks.
+                match waker {
+                    Some(waker) => waker.clone_from(cx.waker()),
+                    None => *waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+            _ => Poll::Ready(take(&mut state)),
+        }
+    }
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn finish(&self, result: io::Result<T>) {
+        let waker = {
+            let mut state = self.lock();
+            match std::mem::replace(&mut *state, State::Done(result)) {
+                State::Running(waker) => waker,
+                _ => None,
+            }
+        };
+        self.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+fn take<T>(state: &mut State<T>) -> io::Result<T> {
+    match std::mem::replace(state, State::Taken) {
+        State::Done(result) => result,
+        State::Running(_) => unreachable!("the scan is still running"),
+        State::Taken => panic!("the scan result was already taken"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::task::Wake;
+    use std::time::Duration;
+
+    /// A minimal executor: parks the thread until the waker fires.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn wait_returns_the_answer() {
+        let scan = PendingScan::spawn(|| Ok(vec![22, 80]));
+        assert_eq!(scan.wait().unwrap(), vec![22, 80]);
+
+        let failed: PendingScan<()> =
+            PendingScan::spawn(|| Err(io::Error::new(io::ErrorKind::NotFound, "no ss")));
+        assert_eq!(failed.wait().unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn try_poll_does_not_block() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let mut scan = PendingScan::spawn(move || {
+            rx.recv().ok();
+            Ok(443)
+        });
+
+        assert!(scan.try_poll().is_none());
+        assert!(!scan.is_finished());
+        tx.send(()).unwrap();
+
+        let answer = loop {
+            if let Some(answer) = scan.try_poll() {
+                break answer;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(answer.unwrap(), 443);
+    }
+
+    #[test]
+    fn future_is_woken_by_the_helper_thread() {
+        let scan = PendingScan::spawn(|| {
+            thread::sleep(Duration::from_millis(20));
+            Ok("done")
+        });
+        assert_eq!(block_on(scan).unwrap(), "done");
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let scan: PendingScan<()> = PendingScan::spawn(|| panic!("backend bug"));
+        assert_eq!(scan.wait().unwrap_err().kind(), io::ErrorKind::Other);
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt;
+use std::ops::{BitAnd, BitOr, RangeInclusive, Sub};
+
+const WORDS: usize = 65536 / 64;
+
+/// A set of ports stored as a 65,536-bit bitmap (8 KiB).
+///
+/// Membership tests are O(1), set algebra is a word-wise pass, and the set
+/// never allocates more than its one fixed buffer, however many ports it
+/// holds. `a | b`, `a & b` and `a - b` are the union, intersection and
+/// difference.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PortSet {
+    words: Box<[u64; WORDS]>,
+}
+
+impl PortSet {
+    /// An empty set.
+    pub fn new() -> PortSet {
+        PortSet {
+            words: Box::new([0; WORDS]),
+        }
+    }
+
+    /// Every port of `range`.
+    pub fn from_range(range: RangeInclusive<u16>) -> PortSet {
+        let mut set = PortSet::new();
+        set.insert_range(range);
+        set
+    }
+
+    /// Adds `port`; returns whether it was not already present.
+    pub fn insert(&mut self, port: u16) -> bool {
+        let (word, bit) = locate(port);
+        let absent = self.words[word] & bit == 0;
+        self.words[word] |= bit;
+        absent
+    }
+
+    /// Adds every port of `range`.
+    pub fn insert_range(&mut self, range: RangeInclusive<u16>) {
+        for port in range {
+            self.insert(port);
+        }
+    }
+
+    /// Removes `port`; returns whether it was present.
+    pub fn remove(&mut self, port: u16) -> bool {
+        let (word, bit) = locate(port);
+        let present = self.words[word] & bit != 0;
+        self.words[word] &= !bit;
+        present
+    }
+
+    /// Whether `port` is in the set.
+    pub fn contains(&self, port: u16) -> bool {
+        let (word, bit) = locate(port);
+        self.words[word] & bit != 0
+    }
+
+    /// Number of ports in the set.
+    pub fn len(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Whether the set holds no port at all.
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    /// Ports in either set.
+    pub fn union(&self, other: &PortSet) -> PortSet {
+        self.zip(other, |a, b| a | b)
+    }
+
+    /// Ports in both sets.
+    pub fn intersection(&self, other: &PortSet) -> PortSet {
+        self.zip(other, |a, b| a & b)
+    }
+
+    /// Ports in `self` but not in `other`.
+    pub fn difference(&self, other: &PortSet) -> PortSet {
+        self.zip(other, |a, b| a & !b)
+    }
+
+    /// Every port in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, word)| {
+            let mut bits = *word;
+            std::iter::from_fn(move || {
+                if bits == 0 {
+                    return None;
+                }
+                let offset = bits.trailing_zeros();
+                bits &= bits - 1;
+                Some((index * 64) as u16 + offset as u16)
+            })
+        })
+    }
+
+    /// The ports of the set within `range`, in ascending order.
+    pub fn iter_range(&self, range: RangeInclusive<u16>) -> impl Iterator<Item = u16> + '_ {
+        range.filter(|port| self.contains(*port))
+    }
+
+    /// Runs of consecutive ports, as inclusive ranges in ascending order.
+    pub fn ranges(&self) -> impl Iterator<Item = RangeInclusive<u16>> + '_ {
+        let mut ports = self.iter().peekable();
+        std::iter::from_fn(move || {
+            let start = ports.next()?;
+            let mut end = start;
+            while ports
+                .next_if(|port| end.checked_add(1) == Some(*port))
+                .is_some()
+            {
+                end += 1;
+            }
+            Some(start..=end)
+        })
+    }
+
+    /// The set as a sorted `Vec`, or `None` if it is empty, matching the
+    /// port functions' return type.
+    pub fn to_vec(&self) -> Option<Vec<u16>> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.iter().collect())
+        }
+    }
+
+    fn zip(&self, other: &PortSet, op: impl Fn(u64, u64) -> u64) -> PortSet {
+        let mut out = PortSet::new();
+        for (word, (a, b)) in out
+            .words
+            .iter_mut()
+            .zip(self.words.iter().zip(other.words.iter()))
+        {
+            *word = op(*a, *b);
+        }
+        out
+    }
+}
+
+impl Default for PortSet {
+    fn default() -> PortSet {
+        PortSet::new()
+    }
+}
+
+impl fmt::Debug for PortSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.ranges()).finish()
+    }
+}
+
+impl FromIterator<u16> for PortSet {
+    fn from_iter<I: IntoIterator<Item = u16>>(iter: I) -> PortSet {
+        let mut set = PortSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<u16> for PortSet {
+    fn extend<I: IntoIterator<Item = u16>>(&mut self, iter: I) {
+        for port in iter {
+            self.insert(port);
+        }
+    }
+}
+
+impl BitOr for &PortSet {
+    type Output = PortSet;
+
+    fn bitor(self, other: &PortSet) -> PortSet {
+        self.union(other)
+    }
+}
+
+impl BitAnd for &PortSet {
+    type Output = PortSet;
+
+    fn bitand(self, other: &PortSet) -> PortSet {
+        self.intersection(other)
+    }
+}
+
+impl Sub for &PortSet {
+    type Output = PortSet;
+
+    fn sub(self, other: &PortSet) -> PortSet {
+        self.difference(other)
+    }
+}
+
+fn locate(port: u16) -> (usize, u64) {
+    (usize::from(port) / 64, 1 << (port % 64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_remove_contains() {
+        let mut set = PortSet::new();
+        assert!(set.is_empty());
+        assert!(set.insert(0));
+        assert!(set.insert(65535));
+        assert!(!set.insert(65535));
+        assert!(set.contains(0) && set.contains(65535));
+        assert!(!set.contains(1));
+        assert_eq!(set.len(), 2);
+        assert!(set.remove(0));
+        assert!(!set.remove(0));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![65535]);
+    }
+
+    #[test]
+    fn set_algebra() {
+        let tcp: PortSet = [22, 80, 443, 8080].into_iter().collect();
+        let udp: PortSet = [53, 443, 5353].into_iter().collect();
+
+        assert_eq!(
+            (&tcp | &udp).iter().collect::<Vec<_>>(),
+            vec![22, 53, 80, 443, 5353, 8080]
+        );
+        assert_eq!((&tcp & &udp).to_vec(), Some(vec![443]));
+        assert_eq!((&tcp - &udp).to_vec(), Some(vec![22, 80, 8080]));
+
+        let free_both = &PortSet::from_range(1024..=65535) - &(&tcp | &udp);
+        assert_eq!(free_both.len(), 64512 - 2);
+        assert!(!free_both.contains(8080) && !free_both.contains(5353));
+    }
+
+    #[test]
+    fn ranges_and_range_iteration() {
+        let set: PortSet = [1, 2, 3, 63, 64, 65, 100, 65534, 65535]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            set.ranges().collect::<Vec<_>>(),
+            vec![1..=3, 63..=65, 100..=100, 65534..=65535]
+        );
+        assert_eq!(
+            set.iter_range(3..=100).collect::<Vec<_>>(),
+            vec![3, 63, 64, 65, 100]
+        );
+        assert_eq!(format!("{:?}", PortSet::from_range(10..=12)), "{10..=12}");
+
+        let full = PortSet::from_range(0..=65535);
+        assert_eq!(full.len(), 65536);
+        assert_eq!(full.ranges().collect::<Vec<_>>(), vec![0..=65535]);
+        assert_eq!(PortSet::new().to_vec(), None);
+    }
+}
@@ -0,0 +1,349 @@
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+
+use crate::error::WalledError;
+use crate::socket::{Family, Protocol, Socket, SocketState};
+
+/// Value of the `st` column for a TCP socket in the `LISTEN` state.
+pub(crate) const TCP_LISTEN: u8 = 0x0A;
+
+/// Value of the `st` column for an unconnected UDP socket (`UNCONN` in `ss`).
+pub(crate) const UDP_UNCONN: u8 = 0x07;
+
+/// One row of a `/proc/net/{tcp,tcp6,udp,udp6}` table.
+///
+/// Addresses are decoded from the kernel's hexadecimal representation and
+/// ports are reported in host byte order, exactly as `ss -n` would print them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcNetEntry {
+    /// Local address the socket is bound to.
+    pub local_addr: IpAddr,
+    /// Local port.
+    pub local_port: u16,
+    /// Remote address (`0.0.0.0` / `::` for unconnected sockets).
+    pub remote_addr: IpAddr,
+    /// Remote port (`0` for unconnected sockets).
+    pub remote_port: u16,
+    /// Raw kernel state code from the `st` column (e.g. `0x0A` = `LISTEN`).
+    pub state: u8,
+    /// Bytes queued for transmission (`tx_queue`).
+    pub tx_queue: u32,
+    /// Bytes queued for reception (`rx_queue`).
+    pub rx_queue: u32,
+    /// Effective UID of the socket owner.
+    pub uid: u32,
+    /// Socket inode, as found under `/proc/<pid>/fd`.
+    pub inode: u64,
+    /// Datagrams dropped by the kernel for this socket (`drops` column).
+    ///
+    /// Only the UDP tables carry this column; TCP rows report `None`.
+    pub drops: Option<u64>,
+}
+
+/// Parses the contents of a `/proc/net/{tcp,tcp6,udp,udp6}` table.
+///
+/// The header line is skipped; both IPv4 (8 hex digits) and IPv6 (32 hex
+/// digits) addresses are accepted. When the header ends in a `drops` column
+/// (the UDP tables), the last field of every row is reported as
+/// [`ProcNetEntry::drops`].
+///
+/// Returns a [`WalledError::Parse`] (`ErrorKind::InvalidData`) naming the
+/// first row that does not have the expected shape.
+pub fn parse_proc_net(contents: &str) -> io::Result<Vec<ProcNetEntry>> {
+    let mut entries = Vec::new();
+    let mut has_drops = false;
+
+    for (index, line) in contents.lines().enumerate() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() {
+            continue;
+        }
+        if parts[0] == "sl" {
+            has_drops = parts.last() == Some(&"drops");
+            continue;
+        }
+        entries.push(parse_row(&parts, has_drops).ok_or_else(|| invalid_row(index + 1, line))?);
+    }
+
+    Ok(entries)
+}
+
+/// Reads and parses `/proc/net/tcp` and `/proc/net/tcp6`.
+///
+/// A missing `tcp6` table (IPv6 disabled at boot) is not an error; a missing
+/// `tcp` table is reported as [`WalledError::BackendNotFound`]
+/// (`ErrorKind::NotFound`).
+pub fn read_tcp_entries() -> io::Result<Vec<ProcNetEntry>> {
+    read_tables(Path::new("/proc/net/tcp"), Path::new("/proc/net/tcp6"))
+}
+
+/// Reads and parses `/proc/net/udp` and `/proc/net/udp6`.
+///
+/// Every entry carries the per-socket `drops` counter alongside the
+/// `rx_queue`/`tx_queue` sizes. A missing `udp6` table is not an error.
+pub fn read_udp_entries() -> io::Result<Vec<ProcNetEntry>> {
+    read_tables(Path::new("/proc/net/udp"), Path::new("/proc/net/udp6"))
+}
+
+/// Every listening socket for `protocol`, IPv4 and IPv6 combined.
+///
+/// For UDP this means unconnected sockets, which is what `ss -uln` lists.
+pub(crate) fn listening_sockets(protocol: Protocol) -> io::Result<Vec<Socket>> {
+    let (entries, state) = match protocol {
+        Protocol::Tcp => (read_tcp_entries()?, TCP_LISTEN),
+        Protocol::Udp => (read_udp_entries()?, UDP_UNCONN),
+    };
+
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.state == state)
+        .map(|entry| entry.into_socket(protocol))
+        .collect())
+}
+
+impl ProcNetEntry {
+    /// Converts the raw row into a [`Socket`] of the given protocol.
+    pub(crate) fn into_socket(self, protocol: Protocol) -> Socket {
+        let remote = if self.remote_addr.is_unspecified() && self.remote_port == 0 {
+            None
+        } else {
+            Some(SocketAddr::new(self.remote_addr, self.remote_port))
+        };
+
+        Socket {
+            protocol,
+            family: Family::of(&self.local_addr),
+            local_addr: self.local_addr,
+            port: self.local_port,
+            remote,
+            state: SocketState::from_kernel(self.state),
+            recv_q: self.rx_queue,
+            send_q: self.tx_queue,
+            inode: Some(self.inode),
+            uid: Some(self.uid),
+            v6only: None,
+            interface: None,
+        }
+    }
+}
+
+fn read_tables(v4: &Path, v6: &Path) -> io::Result<Vec<ProcNetEntry>> {
+    let contents = fs::read_to_string(v4).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => WalledError::BackendNotFound {
+            backend: "procfs".to_string(),
+        }
+        .into(),
+        _ => read_error(e, v4),
+    })?;
+    let mut entries = parse_proc_net(&contents)?;
+
+    match fs::read_to_string(v6) {
+        Ok(contents) => entries.extend(parse_proc_net(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(read_error(e, v6)),
+    }
+
+    Ok(entries)
+}
+
+/// Reports a refused read as [`WalledError::PermissionDenied`].
+fn read_error(e: io::Error, path: &Path) -> io::Error {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        WalledError::PermissionDenied {
+            operation: format!("read {}", path.display()),
+        }
+        .into()
+    } else {
+        e
+    }
+}
+
+fn parse_row(parts: &[&str], has_drops: bool) -> Option<ProcNetEntry> {
+    if parts.len() < 10 {
+        return None;
+    }
+
+    let drops = if has_drops {
+        Some(parts.last()?.parse().ok()?)
+    } else {
+        None
+    };
+
+    let (local_addr, local_port) = parse_endpoint(parts[1])?;
+    let (remote_addr, remote_port) = parse_endpoint(parts[2])?;
+    let state = u8::from_str_radix(parts[3], 16).ok()?;
+    let (tx, rx) = parts[4].split_once(':')?;
+
+    Some(ProcNetEntry {
+        local_addr,
+        local_port,
+        remote_addr,
+        remote_port,
+        state,
+        tx_queue: u32::from_str_radix(tx, 16).ok()?,
+        rx_queue: u32::from_str_radix(rx, 16).ok()?,
+        uid: parts[7].parse().ok()?,
+        inode: parts[9].parse().ok()?,
+        drops,
+    })
+}
+
+/// Decodes an `ADDR:PORT` pair such as `0100007F:0016`.
+///
+/// The kernel prints each 32-bit word of the address in host byte order, so
+/// the words are converted back with `to_ne_bytes` to recover network order.
+fn parse_endpoint(field: &str) -> Option<(IpAddr, u16)> {
+    let (addr, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+
+    let addr = match addr.len() {
+        8 => {
+            let word = u32::from_str_radix(addr, 16).ok()?;
+            IpAddr::V4(Ipv4Addr::from(word.to_ne_bytes()))
+        }
+        32 => {
+            let mut octets = [0u8; 16];
+            for (i, chunk) in octets.chunks_exact_mut(4).enumerate() {
+                let word = u32::from_str_radix(&addr[i * 8..i * 8 + 8], 16).ok()?;
+                chunk.copy_from_slice(&word.to_ne_bytes());
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    Some((addr, port))
+}
+
+fn invalid_row(line_no: usize, line: &str) -> io::Error {
+    WalledError::Parse {
+        line_no,
+        line: line.trim().to_string(),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCP: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:0016 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1234 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 00:00000000 00000000   998        0 5678 1 0000000000000000 100 0 0 10 0
+   2: 0100007F:0CEA 0100007F:BAB4 01 00000010:00000020 00:00000000 00000000   998        0 9012 2 0000000000000000 20 4 0 18 -1
+";
+
+    const TCP6: &str = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000000000000:0050 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 4321 1 0000000000000000 100 0 0 10 0
+   1: 00000000000000000000000001000000:1F90 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 8765 1 0000000000000000 100 0 0 10 0
+";
+
+    const UDP: &str = "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops
+  123: 3500007F:0035 00000000:0000 07 00000000:00000300 00:00000000 00000000   101        0 2468 2 0000000000000000 17
+  456: 00000000:0202 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 1357 2 0000000000000000 0
+";
+
+    #[test]
+    fn parses_ipv4_rows() {
+        let entries = parse_proc_net(TCP).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].local_addr, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(entries[0].local_port, 22);
+        assert_eq!(entries[0].state, TCP_LISTEN);
+        assert_eq!(entries[0].inode, 1234);
+
+        assert_eq!(entries[2].local_addr, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(entries[2].remote_port, 0xBAB4);
+        assert_eq!(entries[2].tx_queue, 0x10);
+        assert_eq!(entries[2].rx_queue, 0x20);
+        assert_eq!(entries[2].uid, 998);
+        assert_eq!(entries[2].drops, None);
+    }
+
+    #[test]
+    fn parses_udp_drops_and_queues() {
+        let entries = parse_proc_net(UDP).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(
+            entries[0].local_addr,
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 53))
+        );
+        assert_eq!(entries[0].local_port, 53);
+        assert_eq!(entries[0].state, UDP_UNCONN);
+        assert_eq!(entries[0].rx_queue, 0x300);
+        assert_eq!(entries[0].drops, Some(17));
+
+        assert_eq!(entries[1].local_port, 514);
+        assert_eq!(entries[1].drops, Some(0));
+    }
+
+    #[test]
+    fn converts_entries_to_sockets() {
+        let entries = parse_proc_net(TCP).unwrap();
+
+        let listener = entries[1].clone().into_socket(Protocol::Tcp);
+        assert_eq!(listener.family, Family::V4);
+        assert_eq!(listener.local(), "127.0.0.1:3306".parse().unwrap());
+        assert_eq!(listener.remote, None);
+        assert_eq!(listener.state, SocketState::Listen);
+        assert_eq!(listener.uid, Some(998));
+        assert_eq!(listener.inode, Some(5678));
+
+        let conn = entries[2].clone().into_socket(Protocol::Tcp);
+        assert_eq!(conn.remote, Some("127.0.0.1:47796".parse().unwrap()));
+        assert_eq!(conn.state, SocketState::Established);
+        assert_eq!((conn.recv_q, conn.send_q), (0x20, 0x10));
+    }
+
+    #[test]
+    fn parses_ipv6_rows() {
+        let entries = parse_proc_net(TCP6).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].local_addr, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        assert_eq!(entries[0].local_port, 80);
+        assert_eq!(entries[1].local_addr, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(entries[1].local_port, 8080);
+    }
+
+    #[test]
+    fn rejects_malformed_rows() {
+        let err = parse_proc_net(&format!("{}   1: 00000000:0016 garbage\n", TCP)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        match WalledError::of(&err) {
+            Some(WalledError::Parse { line_no, line }) => {
+                assert_eq!(*line_no, 5);
+                assert_eq!(line, "1: 00000000:0016 garbage");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_both_tables() {
+        let dir = std::env::temp_dir().join(format!("walled-procfs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (v4, v6) = (dir.join("tcp"), dir.join("tcp6"));
+        fs::write(&v4, TCP).unwrap();
+        fs::write(&v6, TCP6).unwrap();
+
+        let entries = read_tables(&v4, &v6).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[3].local_port, 80);
+
+        // Without IPv6 only the IPv4 table is read.
+        fs::remove_file(&v6).unwrap();
+        assert_eq!(read_tables(&v4, &v6).unwrap().len(), 3);
+
+        fs::remove_file(&v4).unwrap();
+        let err = read_tables(&v4, &v6).unwrap_err();
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::BackendNotFound { backend }) if backend == "procfs"
+        ));
+        fs::remove_dir(&dir).unwrap();
+    }
+}
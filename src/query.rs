@@ -0,0 +1,400 @@
+use std::io;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+
+use crate::pending::PendingScan;
+use crate::portset::PortSet;
+use crate::snapshot::Snapshot;
+use crate::socket::{Family, Protocol, Socket, SocketState};
+use crate::source::SourceChain;
+use crate::sysctl::PortLayout;
+
+/// Whether a [`PortQuery`] reports the ports in use or the free ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryMode {
+    /// Ports some matching socket is bound to.
+    Used,
+    /// Ports of the range no matching socket is bound to.
+    Free,
+}
+
+/// The port range of a query, resolved against the host's [`PortLayout`]
+/// only when it is one of the named ranges.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Range {
+    Ports(RangeInclusive<u16>),
+    Privileged,
+    Unprivileged,
+}
+
+/// A port question, built up step by step and answered in one scan.
+///
+/// Every port function of this crate is a [`PortQuery`] with a fixed
+/// protocol and range; build one yourself for anything else:
+///
+/// ```no_run
+/// use walled::{Family, PortQuery};
+///
+/// // TCP ports 30000-32767 nothing listens on over IPv4.
+/// let free = PortQuery::tcp()
+///     .range(30000..=32767)
+///     .family(Family::V4)
+///     .free()
+///     .run()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// A query starts out as "TCP and UDP, ports 1‑65535, used". With several
+/// protocols, used ports are those used by any of them and free ports are
+/// those free in all of them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PortQuery {
+    protocols: Vec<Protocol>,
+    family: Option<Family>,
+    range: Range,
+    state: Option<SocketState>,
+    bind_addr: Option<IpAddr>,
+    mode: QueryMode,
+    without_ephemeral: bool,
+    without_reserved: bool,
+}
+
+impl PortQuery {
+    /// Used TCP and UDP ports in 1‑65535.
+    pub fn new() -> PortQuery {
+        PortQuery {
+            protocols: vec![Protocol::Tcp, Protocol::Udp],
+            family: None,
+            range: Range::Ports(1..=65535),
+            state: None,
+            bind_addr: None,
+            mode: QueryMode::Used,
+            without_ephemeral: false,
+            without_reserved: false,
+        }
+    }
+
+    /// A query over TCP sockets only.
+    pub fn tcp() -> PortQuery {
+        PortQuery::new().protocol(Protocol::Tcp)
+    }
+
+    /// A query over UDP sockets only.
+    pub fn udp() -> PortQuery {
+        PortQuery::new().protocol(Protocol::Udp)
+    }
+
+    /// Only looks at `protocol` sockets.
+    pub fn protocol(self, protocol: Protocol) -> PortQuery {
+        self.protocols([protocol])
+    }
+
+    /// Looks at sockets of every protocol in `protocols`.
+    pub fn protocols(mut self, protocols: impl IntoIterator<Item = Protocol>) -> PortQuery {
+        self.protocols = protocols.into_iter().collect();
+        self.protocols.sort_unstable();
+        self.protocols.dedup();
+        self
+    }
+
+    /// Only looks at sockets of address family `family`.
+    pub fn family(mut self, family: Family) -> PortQuery {
+        self.family = Some(family);
+        self
+    }
+
+    /// Restricts the answer to the ports of `range`.
+    pub fn range(mut self, range: RangeInclusive<u16>) -> PortQuery {
+        self.range = Range::Ports(range);
+        self
+    }
+
+    /// Restricts the answer to the ports below
+    /// `net.ipv4.ip_unprivileged_port_start`.
+    pub fn privileged(mut self) -> PortQuery {
+        self.range = Range::Privileged;
+        self
+    }
+
+    /// Restricts the answer to the ports from
+    /// `net.ipv4.ip_unprivileged_port_start` upwards.
+    pub fn unprivileged(mut self) -> PortQuery {
+        self.range = Range::Unprivileged;
+        self
+    }
+
+    /// Only looks at sockets in `state`.
+    ///
+    /// The backends only report listening sockets, `LISTEN` for TCP and
+    /// `UNCONN` for UDP, so with [`PortQuery::run`] any other state matches
+    /// nothing. Other states only make sense with [`PortQuery::evaluate`] on
+    /// a snapshot built with [`Snapshot::from_sockets`].
+    pub fn state(mut self, state: SocketState) -> PortQuery {
+        self.state = Some(state);
+        self
+    }
+
+    /// Only counts sockets that stop a new socket from binding `addr`, as
+    /// decided by [`Socket::conflicts_with`].
+    pub fn bind_addr(mut self, addr: IpAddr) -> PortQuery {
+        self.bind_addr = Some(addr);
+        self
+    }
+
+    /// Sets whether used or free ports are reported.
+    pub fn mode(mut self, mode: QueryMode) -> PortQuery {
+        self.mode = mode;
+        self
+    }
+
+    /// Reports the ports in use (the default).
+    pub fn used(self) -> PortQuery {
+        self.mode(QueryMode::Used)
+    }
+
+    /// Reports the ports not in use.
+    pub fn free(self) -> PortQuery {
+        self.mode(QueryMode::Free)
+    }
+
+    /// Leaves out ports in `net.ipv4.ip_local_port_range`.
+    pub fn without_ephemeral(mut self) -> PortQuery {
+        self.without_ephemeral = true;
+        self
+    }
+
+    /// Leaves out ports in `net.ipv4.ip_local_reserved_ports`.
+    pub fn without_reserved(mut self) -> PortQuery {
+        self.without_reserved = true;
+        self
+    }
+
+    /// Answers the query with the default [`SourceChain`].
+    ///
+    /// Success variants:
+    ///   * `Ok(Some(vec))` – the sorted ports that match.
+    ///   * `Ok(None)`      – the scan ran fine but no port matches (empty set).
+    ///
+    /// Failure variant:
+    ///   * `Err(e)` – none of the backends could be queried, their output could
+    ///     not be parsed, or a sysctl the query needs could not be read.
+    pub fn run(&self) -> io::Result<Option<Vec<u16>>> {
+        Ok(self.run_set()?.to_vec())
+    }
+
+    /// Like [`PortQuery::run`], but returns a [`PortSet`].
+    pub fn run_set(&self) -> io::Result<PortSet> {
+        self.run_from(&SourceChain::default())
+    }
+
+    /// Answers the query with the sockets of `chain`.
+    ///
+    /// Only the protocols the query asks about are scanned, and the sysctls
+    /// are only read when a named range or an exclusion needs them.
+    pub fn run_from(&self, chain: &SourceChain) -> io::Result<PortSet> {
+        let mut sockets = Vec::new();
+        for protocol in &self.protocols {
+            sockets.extend(chain.listening_sockets(*protocol)?.sockets);
+        }
+
+        let layout = if self.needs_layout() {
+            PortLayout::current()?
+        } else {
+            PortLayout::default()
+        };
+
+        Ok(self.select(&sockets, &layout))
+    }
+
+    /// Like [`PortQuery::run_set`], but scans on a helper thread; see
+    /// [`PendingScan`].
+    pub fn spawn(&self) -> PendingScan<PortSet> {
+        self.spawn_from(SourceChain::default())
+    }
+
+    /// Like [`PortQuery::run_from`], but scans on a helper thread; see
+    /// [`PendingScan`].
+    pub fn spawn_from(&self, chain: SourceChain) -> PendingScan<PortSet> {
+        let query = self.clone();
+        PendingScan::spawn(move || query.run_from(&chain))
+    }
+
+    /// Answers the query from `snapshot`, using its [`PortLayout`].
+    pub fn evaluate(&self, snapshot: &Snapshot) -> PortSet {
+        self.select(snapshot.sockets(), snapshot.layout())
+    }
+
+    fn needs_layout(&self) -> bool {
+        !matches!(self.range, Range::Ports(_)) || self.without_ephemeral || self.without_reserved
+    }
+
+    fn range_in(&self, layout: &PortLayout) -> RangeInclusive<u16> {
+        match &self.range {
+            Range::Ports(range) => range.clone(),
+            Range::Privileged => layout.privileged_range(),
+            Range::Unprivileged => layout.unprivileged_range(),
+        }
+    }
+
+    /// The shared execution path: filters `sockets`, applies the mode within
+    /// the resolved range, then the exclusions.
+    fn select(&self, sockets: &[Socket], layout: &PortLayout) -> PortSet {
+        let range = PortSet::from_range(self.range_in(layout));
+
+        let mut answer = match self.mode {
+            QueryMode::Used => {
+                let used: PortSet = sockets
+                    .iter()
+                    .filter(|socket| self.matches(socket))
+                    .map(|socket| socket.port)
+                    .collect();
+                &used & &range
+            }
+            QueryMode::Free => {
+                let mut free = range;
+                for socket in sockets.iter().filter(|socket| self.matches(socket)) {
+                    free.remove(socket.port);
+                }
+                free
+            }
+        };
+
+        if self.without_ephemeral {
+            for port in layout.ephemeral() {
+                answer.remove(port);
+            }
+        }
+        if self.without_reserved {
+            answer = &answer - &PortSet::from(layout.reserved());
+        }
+
+        answer
+    }
+
+    fn matches(&self, socket: &Socket) -> bool {
+        self.protocols.contains(&socket.protocol)
+            && self.family.is_none_or(|family| socket.family == family)
+            && self.state.is_none_or(|state| socket.state == state)
+            && self
+                .bind_addr
+                .is_none_or(|addr| socket.conflicts_with(addr))
+    }
+}
+
+impl Default for PortQuery {
+    fn default() -> PortQuery {
+        PortQuery::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_ss_output;
+    use crate::source::SsSource;
+
+    fn sample() -> Snapshot {
+        let mut sockets = parse_ss_output(
+            "LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n\
+             LISTEN 0 128 127.0.0.1:631 0.0.0.0:*\n\
+             LISTEN 0 128 [::1]:6379 [::]:*\n\
+             LISTEN 0 128 *:31000 *:*\n\
+             ESTAB 0 0 10.0.0.5:22 10.0.0.9:51234\n",
+            Protocol::Tcp,
+        );
+        sockets.extend(parse_ss_output(
+            "UNCONN 0 0 0.0.0.0:53 0.0.0.0:*\n\
+             UNCONN 0 0 0.0.0.0:31001 0.0.0.0:*\n",
+            Protocol::Udp,
+        ));
+        Snapshot::from_sockets(sockets)
+    }
+
+    fn ports(set: PortSet) -> Vec<u16> {
+        set.iter().collect()
+    }
+
+    #[test]
+    fn protocols_and_modes() {
+        let snap = sample();
+        assert_eq!(
+            ports(PortQuery::new().evaluate(&snap)),
+            vec![22, 53, 631, 6379, 31000, 31001]
+        );
+        assert_eq!(ports(PortQuery::udp().evaluate(&snap)), vec![53, 31001]);
+        assert_eq!(
+            ports(PortQuery::new().range(30999..=31002).free().evaluate(&snap)),
+            vec![30999, 31002]
+        );
+        assert_eq!(
+            ports(PortQuery::tcp().range(30999..=31002).free().evaluate(&snap)),
+            vec![30999, 31001, 31002]
+        );
+    }
+
+    #[test]
+    fn socket_filters() {
+        let snap = sample();
+        assert_eq!(
+            ports(PortQuery::tcp().family(Family::V6).evaluate(&snap)),
+            vec![6379, 31000]
+        );
+        assert_eq!(
+            ports(
+                PortQuery::tcp()
+                    .state(SocketState::Established)
+                    .evaluate(&snap)
+            ),
+            vec![22]
+        );
+        assert_eq!(
+            ports(
+                PortQuery::tcp()
+                    .bind_addr("10.0.0.7".parse().unwrap())
+                    .evaluate(&snap)
+            ),
+            vec![22, 31000]
+        );
+    }
+
+    #[test]
+    fn named_ranges_and_exclusions_follow_the_layout() {
+        let layout =
+            PortLayout::new(600, 31000..=40000).with_reserved([6379..=6380].into_iter().collect());
+        let snap = sample().with_layout(layout);
+
+        assert_eq!(
+            ports(PortQuery::tcp().privileged().evaluate(&snap)),
+            vec![22]
+        );
+        assert_eq!(
+            ports(PortQuery::tcp().unprivileged().evaluate(&snap)),
+            vec![631, 6379, 31000]
+        );
+
+        let free = PortQuery::tcp()
+            .unprivileged()
+            .free()
+            .without_ephemeral()
+            .without_reserved()
+            .evaluate(&snap);
+        assert!(free.contains(600) && free.contains(40001));
+        assert!(!free.contains(631) && !free.contains(6380) && !free.contains(31001));
+        assert_eq!(free.len(), (65535 - 600 + 1) - 1 - 2 - 9001);
+    }
+
+    #[test]
+    fn run_from_scans_only_the_requested_protocols() {
+        let runner = |_: &str, args: &[&str]| match args {
+            ["-tlnH"] => Ok("LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n".to_string()),
+            _ => panic!("unexpected arguments {:?}", args),
+        };
+        let chain = SourceChain::new().with(SsSource::with_runner(runner));
+
+        let used = PortQuery::tcp().range(1..=1023).run_from(&chain).unwrap();
+        assert_eq!(ports(used), vec![22]);
+
+        let pending = PortQuery::tcp().range(1..=1023).spawn_from(chain);
+        assert_eq!(ports(pending.wait().unwrap()), vec![22]);
+    }
+}
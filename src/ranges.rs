@@ -0,0 +1,333 @@
+use std::fmt;
+use std::io;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::error::WalledError;
+use crate::portset::PortSet;
+
+/// A set of ports stored as sorted, non-overlapping inclusive ranges, the
+/// way the kernel lists them in `ip_local_reserved_ports`.
+///
+/// Overlapping and adjacent ranges are merged on insertion, so
+/// `8000-8100` and `8101-8200` become a single `8000-8200`.
+///
+/// It is also the compact way to show a port list: `Display` prints
+/// `1024-3305,3307-8079,9090` and `FromStr` parses the same syntax back, so
+/// results round-trip through logs, config files and CLI arguments.
+///
+/// ```
+/// use walled::PortRanges;
+///
+/// let free = PortRanges::from_ports([1024, 1025, 1026, 3000, 3002]);
+/// assert_eq!(free.to_string(), "1024-1026,3000,3002");
+/// assert_eq!("1024-1026,3000,3002".parse::<PortRanges>().unwrap(), free);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PortRanges {
+    ranges: Vec<RangeInclusive<u16>>,
+}
+
+impl PortRanges {
+    /// An empty set.
+    pub fn new() -> PortRanges {
+        PortRanges::default()
+    }
+
+    /// Collapses a list of ports, in any order and with duplicates, into
+    /// ranges.
+    pub fn from_ports(ports: impl IntoIterator<Item = u16>) -> PortRanges {
+        let mut ports: Vec<u16> = ports.into_iter().collect();
+        ports.sort_unstable();
+        ports.dedup();
+
+        let mut ranges: Vec<RangeInclusive<u16>> = Vec::new();
+        for port in ports {
+            match ranges.last_mut() {
+                Some(last) if last.end().checked_add(1) == Some(port) => {
+                    *last = *last.start()..=port;
+                }
+                _ => ranges.push(port..=port),
+            }
+        }
+
+        PortRanges { ranges }
+    }
+
+    /// Adds every port of `range`; empty ranges are ignored.
+    pub fn insert(&mut self, range: RangeInclusive<u16>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let (mut start, mut end) = range.into_inner();
+        let mut merged = Vec::with_capacity(self.ranges.len() + 1);
+        let mut placed = false;
+
+        for existing in self.ranges.drain(..) {
+            let (s, e) = existing.into_inner();
+            if u32::from(e) + 1 < u32::from(start) {
+                merged.push(s..=e);
+            } else if u32::from(end) + 1 < u32::from(s) {
+                if !placed {
+                    merged.push(start..=end);
+                    placed = true;
+                }
+                merged.push(s..=e);
+            } else {
+                start = start.min(s);
+                end = end.max(e);
+            }
+        }
+        if !placed {
+            merged.push(start..=end);
+        }
+
+        self.ranges = merged;
+    }
+
+    /// Removes every port of `range`, splitting ranges that only partly
+    /// overlap it.
+    pub fn remove(&mut self, range: RangeInclusive<u16>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let (start, end) = range.into_inner();
+        let mut kept = Vec::with_capacity(self.ranges.len() + 1);
+
+        for existing in self.ranges.drain(..) {
+            let (s, e) = existing.into_inner();
+            if e < start || s > end {
+                kept.push(s..=e);
+                continue;
+            }
+            if s < start {
+                kept.push(s..=start - 1);
+            }
+            if e > end {
+                kept.push(end + 1..=e);
+            }
+        }
+
+        self.ranges = kept;
+    }
+
+    /// Whether `port` is in the set.
+    pub fn contains(&self, port: u16) -> bool {
+        let idx = self.ranges.partition_point(|range| *range.end() < port);
+        self.ranges
+            .get(idx)
+            .is_some_and(|range| range.contains(&port))
+    }
+
+    /// The merged ranges, in ascending order.
+    pub fn ranges(&self) -> &[RangeInclusive<u16>] {
+        &self.ranges
+    }
+
+    /// Whether the set holds no port at all.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Number of ports in the set.
+    pub fn len(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|range| usize::from(range.end() - range.start()) + 1)
+            .sum()
+    }
+
+    /// Every port in the set, in ascending order.
+    pub fn ports(&self) -> impl Iterator<Item = u16> + '_ {
+        self.ranges.iter().flat_map(|range| range.clone())
+    }
+}
+
+impl FromIterator<RangeInclusive<u16>> for PortRanges {
+    fn from_iter<I: IntoIterator<Item = RangeInclusive<u16>>>(iter: I) -> PortRanges {
+        let mut set = PortRanges::new();
+        for range in iter {
+            set.insert(range);
+        }
+        set
+    }
+}
+
+impl From<&PortSet> for PortRanges {
+    fn from(set: &PortSet) -> PortRanges {
+        PortRanges {
+            ranges: set.ranges().collect(),
+        }
+    }
+}
+
+impl From<&PortRanges> for PortSet {
+    fn from(ranges: &PortRanges) -> PortSet {
+        let mut set = PortSet::new();
+        for range in ranges.ranges() {
+            set.insert_range(range.clone());
+        }
+        set
+    }
+}
+
+impl fmt::Display for PortRanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, range) in self.ranges.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            if range.start() == range.end() {
+                write!(f, "{}", range.start())?;
+            } else {
+                write!(f, "{}-{}", range.start(), range.end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a comma-separated list of ports and `low-high` ranges such as
+/// `1024-3305,3307-8079,9090`. Surrounding whitespace is ignored and an
+/// empty string is an empty set; anything else fails with a
+/// [`WalledError::Parse`] of kind [`io::ErrorKind::InvalidData`].
+impl FromStr for PortRanges {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<PortRanges> {
+        let invalid = || -> io::Error {
+            WalledError::Parse {
+                line_no: 1,
+                line: s.trim().to_string(),
+            }
+            .into()
+        };
+
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(PortRanges::new());
+        }
+
+        let mut ranges = PortRanges::new();
+        for item in s.split(',') {
+            let item = item.trim();
+            let (low, high) = item.split_once('-').unwrap_or((item, item));
+            let low: u16 = low.trim().parse().map_err(|_| invalid())?;
+            let high: u16 = high.trim().parse().map_err(|_| invalid())?;
+            if low > high {
+                return Err(invalid());
+            }
+            ranges.insert(low..=high);
+        }
+
+        Ok(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        let set: PortRanges = [9000..=9000, 8000..=8100, 8050..=8200, 8201..=8300, 1..=1]
+            .into_iter()
+            .collect();
+        assert_eq!(set.ranges(), &[1..=1, 8000..=8300, 9000..=9000]);
+        assert_eq!(set.len(), 1 + 301 + 1);
+
+        let mut set = set;
+        set.insert(2..=7999);
+        assert_eq!(set.ranges(), &[1..=8300, 9000..=9000]);
+    }
+
+    #[test]
+    fn collapses_port_lists() {
+        let ranges = PortRanges::from_ports([3307, 1024, 1025, 8079, 1026, 3307, 65535]);
+        assert_eq!(
+            ranges.ranges(),
+            &[1024..=1026, 3307..=3307, 8079..=8079, 65535..=65535]
+        );
+        assert_eq!(ranges.to_string(), "1024-1026,3307,8079,65535");
+        assert_eq!(PortRanges::from_ports([]).to_string(), "");
+
+        let set = PortSet::from(&ranges);
+        assert_eq!(set.len(), 6);
+        assert_eq!(PortRanges::from(&set), ranges);
+    }
+
+    #[test]
+    fn parses_and_round_trips() {
+        let ranges: PortRanges = "8000-8100,9090,8101-8200\n".parse().unwrap();
+        assert_eq!(ranges.ranges(), &[8000..=8200, 9090..=9090]);
+        assert_eq!(ranges.to_string(), "8000-8200,9090");
+        assert_eq!(ranges.to_string().parse::<PortRanges>().unwrap(), ranges);
+        assert_eq!(
+            " 1024 - 3305, 3307 "
+                .parse::<PortRanges>()
+                .unwrap()
+                .to_string(),
+            "1024-3305,3307"
+        );
+        assert!("\n".parse::<PortRanges>().unwrap().is_empty());
+
+        for bad in [
+            "8000-",
+            "-1",
+            "9000-8000",
+            "80,,81",
+            "http",
+            "1-2-3",
+            "70000",
+        ] {
+            assert_eq!(
+                bad.parse::<PortRanges>().unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn removal_splits_ranges() {
+        let mut set: PortRanges = [0..=10, 20..=30, 40..=65535].into_iter().collect();
+        set.remove(5..=25);
+        assert_eq!(set.ranges(), &[0..=4, 26..=30, 40..=65535]);
+        set.remove(0..=0);
+        set.remove(65535..=65535);
+        assert_eq!(set.ranges(), &[1..=4, 26..=30, 40..=65534]);
+        set.remove(0..=65535);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn handles_the_ends_of_the_port_space() {
+        let set: PortRanges = [65535..=65535, 0..=0, 65000..=65534].into_iter().collect();
+        assert_eq!(set.ranges(), &[0..=0, 65000..=65535]);
+        assert!(set.contains(0));
+        assert!(set.contains(65535));
+        assert!(!set.contains(1));
+        assert_eq!(set.len(), 537);
+    }
+
+    #[test]
+    fn lookups_and_iteration() {
+        let set: PortRanges = [8000..=8002, 9000..=9001].into_iter().collect();
+        assert!(set.contains(8001));
+        assert!(!set.contains(8003));
+        assert!(!set.contains(7999));
+        assert!(set.contains(9001));
+        assert_eq!(
+            set.ports().collect::<Vec<_>>(),
+            vec![8000, 8001, 8002, 9000, 9001]
+        );
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty: PortRanges = [5..=4].into_iter().collect();
+        assert!(empty.is_empty());
+        assert_eq!(empty.len(), 0);
+    }
+}
@@ -0,0 +1,490 @@
+use std::io;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::time::SystemTime;
+
+use crate::bind::blocked_ports;
+use crate::diff::{SnapshotDiff, diff};
+use crate::json::{decode_snapshot, encode_snapshot};
+use crate::portset::PortSet;
+use crate::socket::{Protocol, Socket};
+use crate::source::SourceChain;
+use crate::sysctl::PortLayout;
+
+/// An immutable view of every listening TCP and UDP socket, IPv4 and IPv6
+/// combined, taken in a single scan.
+///
+/// All queries on a snapshot answer from the same data, so e.g. a port can
+/// never show up as both used and free, and asking many questions costs only
+/// one scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    sockets: Vec<Socket>,
+    tcp: Vec<u16>,
+    udp: Vec<u16>,
+    captured_at: SystemTime,
+    layout: PortLayout,
+}
+
+impl Snapshot {
+    /// Scans the host once using the default [`SourceChain`].
+    pub fn capture() -> io::Result<Snapshot> {
+        Snapshot::capture_from(&SourceChain::default())
+    }
+
+    /// Scans the host once using `chain`.
+    ///
+    /// The host's [`PortLayout`] is read alongside the sockets, so the
+    /// privileged/unprivileged and ephemeral queries use its sysctls.
+    pub fn capture_from(chain: &SourceChain) -> io::Result<Snapshot> {
+        let mut sockets = chain.listening_sockets(Protocol::Tcp)?.sockets;
+        sockets.extend(chain.listening_sockets(Protocol::Udp)?.sockets);
+        let layout = PortLayout::current()?;
+        Ok(Snapshot::from_sockets(sockets).with_layout(layout))
+    }
+
+    /// Builds a snapshot from already known listening sockets, e.g. a
+    /// recorded inventory.
+    ///
+    /// The layout defaults to [`PortLayout::default`]; see
+    /// [`Snapshot::with_layout`].
+    pub fn from_sockets(sockets: Vec<Socket>) -> Snapshot {
+        let ports = |protocol| {
+            sorted(
+                sockets
+                    .iter()
+                    .filter(|socket| socket.protocol == protocol)
+                    .map(|socket| socket.port)
+                    .collect(),
+            )
+        };
+
+        Snapshot {
+            tcp: ports(Protocol::Tcp),
+            udp: ports(Protocol::Udp),
+            sockets,
+            captured_at: SystemTime::now(),
+            layout: PortLayout::default(),
+        }
+    }
+
+    /// Uses `start` as the first unprivileged port for the
+    /// `privileged_*` / `unprivileged_*` queries.
+    pub fn with_unprivileged_port_start(mut self, start: u16) -> Snapshot {
+        self.layout = self.layout.with_unprivileged_port_start(start);
+        self
+    }
+
+    /// Uses `layout` for the privileged/unprivileged and ephemeral queries.
+    pub fn with_layout(mut self, layout: PortLayout) -> Snapshot {
+        self.layout = layout;
+        self
+    }
+
+    /// First unprivileged port, as read from
+    /// `net.ipv4.ip_unprivileged_port_start` when the snapshot was captured.
+    pub fn unprivileged_port_start(&self) -> u16 {
+        self.layout.unprivileged_port_start()
+    }
+
+    /// The port layout the snapshot's queries use.
+    pub fn layout(&self) -> &PortLayout {
+        &self.layout
+    }
+
+    /// Encodes the snapshot, layout included, as a versioned JSON document;
+    /// see [`JSON_SCHEMA_VERSION`](crate::JSON_SCHEMA_VERSION).
+    pub fn to_json(&self) -> String {
+        encode_snapshot(self)
+    }
+
+    /// Decodes a document written by [`Snapshot::to_json`], e.g. a stored
+    /// inventory to diff against.
+    ///
+    /// Failure variants:
+    ///   * [`WalledError::Parse`](crate::WalledError::Parse) – the text is
+    ///     not JSON or does not follow the schema.
+    ///   * [`WalledError::Unsupported`](crate::WalledError::Unsupported) –
+    ///     the document has a newer schema version than this crate knows.
+    pub fn from_json(text: &str) -> io::Result<Snapshot> {
+        decode_snapshot(text)
+    }
+
+    pub(crate) fn with_captured_at(mut self, captured_at: SystemTime) -> Snapshot {
+        self.captured_at = captured_at;
+        self
+    }
+
+    /// When the scan was taken.
+    pub fn captured_at(&self) -> SystemTime {
+        self.captured_at
+    }
+
+    /// Every listening socket, TCP first, in the order the backend reported them.
+    pub fn sockets(&self) -> &[Socket] {
+        &self.sockets
+    }
+
+    /// Every listening port for `protocol`, sorted and deduplicated.
+    pub fn listening(&self, protocol: Protocol) -> &[u16] {
+        match protocol {
+            Protocol::Tcp => &self.tcp,
+            Protocol::Udp => &self.udp,
+        }
+    }
+
+    /// Every listening port for `protocol`, as a [`PortSet`].
+    pub fn listening_set(&self, protocol: Protocol) -> PortSet {
+        self.listening(protocol).iter().copied().collect()
+    }
+
+    /// Listening ports for `protocol` within `range`, as a [`PortSet`].
+    pub fn used_set(&self, protocol: Protocol, range: RangeInclusive<u16>) -> PortSet {
+        &self.listening_set(protocol) & &PortSet::from_range(range)
+    }
+
+    /// Non-listening ports for `protocol` within `range`, as a [`PortSet`].
+    ///
+    /// Combine the sets of both protocols to answer questions such as
+    /// "free for TCP and UDP" without building any `Vec`.
+    pub fn free_set(&self, protocol: Protocol, range: RangeInclusive<u16>) -> PortSet {
+        &PortSet::from_range(range) - &self.listening_set(protocol)
+    }
+
+    /// What changed from this snapshot to the later `other`: the sockets
+    /// added, removed, or rebound to another address or owner, by protocol.
+    pub fn diff(&self, other: &Snapshot) -> SnapshotDiff {
+        diff(&self.sockets, &other.sockets)
+    }
+
+    /// Whether anything listens on `port` for `protocol`.
+    pub fn is_used(&self, protocol: Protocol, port: u16) -> bool {
+        self.listening(protocol).binary_search(&port).is_ok()
+    }
+
+    /// Listening ports for `protocol` within `range`, or `None` if there are none.
+    pub fn used(&self, protocol: Protocol, range: RangeInclusive<u16>) -> Option<Vec<u16>> {
+        used_in(self.listening(protocol), range)
+    }
+
+    /// Non-listening ports for `protocol` within `range`, or `None` if every
+    /// port in it is in use.
+    pub fn free(&self, protocol: Protocol, range: RangeInclusive<u16>) -> Option<Vec<u16>> {
+        free_in(self.listening(protocol), range)
+    }
+
+    /// Like [`Snapshot::free`], but leaves out the ephemeral range, where an
+    /// outbound connection could take the port before a service binds it.
+    pub fn free_non_ephemeral(
+        &self,
+        protocol: Protocol,
+        range: RangeInclusive<u16>,
+    ) -> Option<Vec<u16>> {
+        self.layout.without_ephemeral(self.free(protocol, range))
+    }
+
+    /// Like [`Snapshot::free`], but leaves out the ports listed in
+    /// `ip_local_reserved_ports`, which are set aside for specific services.
+    /// Use [`PortLayout::is_reserved`] on [`Snapshot::layout`] to tag them
+    /// instead.
+    pub fn free_unreserved(
+        &self,
+        protocol: Protocol,
+        range: RangeInclusive<u16>,
+    ) -> Option<Vec<u16>> {
+        self.layout.without_reserved(self.free(protocol, range))
+    }
+
+    /// Whether a new `protocol` socket could bind `addr:port`.
+    ///
+    /// Snapshot counterpart of [`is_free_on`](crate::is_free_on).
+    pub fn is_free_on(&self, addr: IpAddr, port: u16, protocol: Protocol) -> bool {
+        !self.sockets.iter().any(|socket| {
+            socket.protocol == protocol && socket.port == port && socket.conflicts_with(addr)
+        })
+    }
+
+    /// Ports of `range` a new `protocol` socket could bind on `addr`, or `None`
+    /// if all of them are blocked.
+    ///
+    /// Snapshot counterpart of [`free_ports_on`](crate::free_ports_on).
+    pub fn free_ports_on(
+        &self,
+        addr: IpAddr,
+        range: RangeInclusive<u16>,
+        protocol: Protocol,
+    ) -> Option<Vec<u16>> {
+        free_in(&blocked_ports(&self.sockets, addr, protocol), range)
+    }
+
+    /// Snapshot counterpart of [`privileged_tcp_used`](crate::privileged_tcp_used).
+    pub fn privileged_tcp_used(&self) -> Option<Vec<u16>> {
+        self.used(Protocol::Tcp, self.privileged_range())
+    }
+
+    /// Snapshot counterpart of [`privileged_tcp_free`](crate::privileged_tcp_free).
+    pub fn privileged_tcp_free(&self) -> Option<Vec<u16>> {
+        self.free(Protocol::Tcp, self.privileged_range())
+    }
+
+    /// Snapshot counterpart of [`unprivileged_tcp_used`](crate::unprivileged_tcp_used).
+    pub fn unprivileged_tcp_used(&self) -> Option<Vec<u16>> {
+        self.used(Protocol::Tcp, self.unprivileged_range())
+    }
+
+    /// Snapshot counterpart of [`unprivileged_tcp_free`](crate::unprivileged_tcp_free).
+    pub fn unprivileged_tcp_free(&self) -> Option<Vec<u16>> {
+        self.free(Protocol::Tcp, self.unprivileged_range())
+    }
+
+    /// Snapshot counterpart of
+    /// [`unprivileged_tcp_free_non_ephemeral`](crate::unprivileged_tcp_free_non_ephemeral).
+    pub fn unprivileged_tcp_free_non_ephemeral(&self) -> Option<Vec<u16>> {
+        self.free_non_ephemeral(Protocol::Tcp, self.unprivileged_range())
+    }
+
+    /// Snapshot counterpart of
+    /// [`unprivileged_tcp_free_unreserved`](crate::unprivileged_tcp_free_unreserved).
+    pub fn unprivileged_tcp_free_unreserved(&self) -> Option<Vec<u16>> {
+        self.free_unreserved(Protocol::Tcp, self.unprivileged_range())
+    }
+
+    /// Snapshot counterpart of [`privileged_udp_used`](crate::privileged_udp_used).
+    pub fn privileged_udp_used(&self) -> Option<Vec<u16>> {
+        self.used(Protocol::Udp, self.privileged_range())
+    }
+
+    /// Snapshot counterpart of [`privileged_udp_free`](crate::privileged_udp_free).
+    pub fn privileged_udp_free(&self) -> Option<Vec<u16>> {
+        self.free(Protocol::Udp, self.privileged_range())
+    }
+
+    /// Snapshot counterpart of [`unprivileged_udp_used`](crate::unprivileged_udp_used).
+    pub fn unprivileged_udp_used(&self) -> Option<Vec<u16>> {
+        self.used(Protocol::Udp, self.unprivileged_range())
+    }
+
+    /// Snapshot counterpart of [`unprivileged_udp_free`](crate::unprivileged_udp_free).
+    pub fn unprivileged_udp_free(&self) -> Option<Vec<u16>> {
+        self.free(Protocol::Udp, self.unprivileged_range())
+    }
+
+    /// Snapshot counterpart of
+    /// [`unprivileged_udp_free_non_ephemeral`](crate::unprivileged_udp_free_non_ephemeral).
+    pub fn unprivileged_udp_free_non_ephemeral(&self) -> Option<Vec<u16>> {
+        self.free_non_ephemeral(Protocol::Udp, self.unprivileged_range())
+    }
+
+    /// Snapshot counterpart of
+    /// [`unprivileged_udp_free_unreserved`](crate::unprivileged_udp_free_unreserved).
+    pub fn unprivileged_udp_free_unreserved(&self) -> Option<Vec<u16>> {
+        self.free_unreserved(Protocol::Udp, self.unprivileged_range())
+    }
+
+    fn privileged_range(&self) -> RangeInclusive<u16> {
+        self.layout.privileged_range()
+    }
+
+    fn unprivileged_range(&self) -> RangeInclusive<u16> {
+        self.layout.unprivileged_range()
+    }
+}
+
+/// Sorts and deduplicates a list of ports.
+pub(crate) fn sorted(mut ports: Vec<u16>) -> Vec<u16> {
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+/// Ports of the sorted `used` list that fall in `range`; `None` if empty.
+pub(crate) fn used_in(used: &[u16], range: RangeInclusive<u16>) -> Option<Vec<u16>> {
+    let ports: Vec<u16> = used
+        .iter()
+        .copied()
+        .filter(|port| range.contains(port))
+        .collect();
+
+    if ports.is_empty() { None } else { Some(ports) }
+}
+
+/// Ports of `range` missing from the sorted `used` list; `None` if empty.
+pub(crate) fn free_in(used: &[u16], range: RangeInclusive<u16>) -> Option<Vec<u16>> {
+    let ports: Vec<u16> = range
+        .filter(|port| used.binary_search(port).is_err())
+        .collect();
+
+    if ports.is_empty() { None } else { Some(ports) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_ss_output;
+    use crate::source::SsSource;
+
+    fn sample() -> Snapshot {
+        let mut sockets = parse_ss_output(
+            "LISTEN 0 128 0.0.0.0:8080 0.0.0.0:*\n\
+             LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n\
+             LISTEN 0 128 0.0.0.0:443 0.0.0.0:*\n\
+             LISTEN 0 128 [::]:22 [::]:*\n",
+            Protocol::Tcp,
+        );
+        sockets.extend(parse_ss_output(
+            "UNCONN 0 0 127.0.0.53%lo:53 0.0.0.0:*\n\
+             UNCONN 0 0 0.0.0.0:5353 0.0.0.0:*\n",
+            Protocol::Udp,
+        ));
+        Snapshot::from_sockets(sockets)
+    }
+
+    #[test]
+    fn queries_share_one_scan() {
+        let snap = sample();
+        assert_eq!(snap.sockets().len(), 6);
+        assert_eq!(snap.listening(Protocol::Tcp), &[22, 443, 8080]);
+        assert!(snap.is_used(Protocol::Tcp, 443));
+        assert!(!snap.is_used(Protocol::Udp, 443));
+
+        assert_eq!(snap.privileged_tcp_used(), Some(vec![22, 443]));
+        assert_eq!(snap.unprivileged_tcp_used(), Some(vec![8080]));
+        assert_eq!(snap.unprivileged_udp_used(), Some(vec![5353]));
+
+        let free = snap.privileged_tcp_free().unwrap();
+        assert_eq!(free.len(), 1023 - 2);
+        assert!(!free.contains(&22));
+        assert!(free.contains(&23));
+    }
+
+    #[test]
+    fn privileged_boundary_follows_sysctl() {
+        let snap = sample().with_unprivileged_port_start(443);
+        assert_eq!(snap.unprivileged_port_start(), 443);
+        assert_eq!(snap.privileged_tcp_used(), Some(vec![22]));
+        assert_eq!(snap.unprivileged_tcp_used(), Some(vec![443, 8080]));
+
+        let open = sample().with_unprivileged_port_start(0);
+        assert_eq!(open.privileged_tcp_used(), None);
+        assert_eq!(open.privileged_tcp_free(), None);
+        assert_eq!(open.unprivileged_udp_used(), Some(vec![53, 5353]));
+    }
+
+    #[test]
+    fn free_ports_can_skip_the_ephemeral_range() {
+        let snap = sample().with_layout(PortLayout::new(1024, 8081..=65000));
+        assert_eq!(
+            snap.free_non_ephemeral(Protocol::Tcp, 8078..=8082),
+            Some(vec![8078, 8079])
+        );
+        assert_eq!(snap.free_non_ephemeral(Protocol::Tcp, 9000..=9001), None);
+
+        let free = snap.unprivileged_tcp_free_non_ephemeral().unwrap();
+        assert_eq!(free.first(), Some(&1024));
+        assert!(!free.contains(&8080));
+        assert!(!free.contains(&8081));
+        assert!(free.contains(&65001));
+    }
+
+    #[test]
+    fn free_ports_can_skip_reserved_ports() {
+        let reserved = [8079..=8079, 8082..=9000].into_iter().collect();
+        let snap = sample().with_layout(PortLayout::default().with_reserved(reserved));
+        assert_eq!(
+            snap.free_unreserved(Protocol::Tcp, 8078..=8082),
+            Some(vec![8078, 8081])
+        );
+        assert!(snap.layout().is_reserved(8079));
+
+        let free = snap.unprivileged_udp_free_unreserved().unwrap();
+        assert_eq!(free.len(), (65535 - 1024 + 1) - 1 - 920);
+
+        // Moving the privileged boundary keeps the reserved ports.
+        let moved = snap.with_unprivileged_port_start(8000);
+        assert!(moved.layout().is_reserved(8079));
+        assert_eq!(
+            moved.free_unreserved(Protocol::Tcp, 8078..=8082),
+            Some(vec![8078, 8081])
+        );
+        let free = moved.unprivileged_tcp_free_unreserved().unwrap();
+        assert!(!free.contains(&8079) && !free.contains(&8500));
+        assert!(free.contains(&8078));
+    }
+
+    #[test]
+    fn port_set_queries() {
+        let snap = sample();
+        assert_eq!(
+            snap.used_set(Protocol::Tcp, 1..=1023).to_vec(),
+            snap.privileged_tcp_used()
+        );
+
+        let both =
+            &snap.free_set(Protocol::Tcp, 1..=1023) & &snap.free_set(Protocol::Udp, 1..=1023);
+        assert_eq!(both.len(), 1023 - 3);
+        assert!(!both.contains(22) && !both.contains(53) && !both.contains(443));
+        assert_eq!(
+            snap.free_set(Protocol::Udp, 1..=65535).to_vec(),
+            snap.free(Protocol::Udp, 1..=65535)
+        );
+    }
+
+    #[test]
+    fn custom_ranges() {
+        let snap = sample();
+        assert_eq!(snap.used(Protocol::Tcp, 400..=9000), Some(vec![443, 8080]));
+        assert_eq!(snap.used(Protocol::Udp, 1..=52), None);
+        assert_eq!(
+            snap.free(Protocol::Tcp, 8079..=8081),
+            Some(vec![8079, 8081])
+        );
+        assert_eq!(snap.free(Protocol::Tcp, 22..=22), None);
+    }
+
+    #[test]
+    fn address_aware_queries() {
+        let snap = sample();
+        let loopback = IpAddr::from([127, 0, 0, 53]);
+        let public = IpAddr::from([10, 0, 0, 5]);
+
+        assert!(!snap.is_free_on(public, 22, Protocol::Tcp));
+        assert!(snap.is_free_on(public, 53, Protocol::Udp));
+        assert!(!snap.is_free_on(loopback, 53, Protocol::Udp));
+        assert_eq!(
+            snap.free_ports_on(public, 52..=54, Protocol::Udp),
+            Some(vec![52, 53, 54])
+        );
+        assert_eq!(
+            snap.free_ports_on(loopback, 52..=54, Protocol::Udp),
+            Some(vec![52, 54])
+        );
+    }
+
+    #[test]
+    fn capture_from_chain() {
+        let runner = |_: &str, args: &[&str]| {
+            Ok(match args {
+                ["-tlnH"] => "LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n",
+                _ => "UNCONN 0 0 0.0.0.0:123 0.0.0.0:*\n",
+            }
+            .to_string())
+        };
+        let chain = SourceChain::new().with(SsSource::with_runner(runner));
+
+        let snap = Snapshot::capture_from(&chain).unwrap();
+        assert_eq!(snap.listening(Protocol::Tcp), &[22]);
+        assert_eq!(snap.listening(Protocol::Udp), &[123]);
+    }
+
+    #[test]
+    fn capture_fails_if_one_protocol_fails() {
+        let runner = |_: &str, args: &[&str]| match args {
+            ["-tlnH"] => Ok("LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n".to_string()),
+            _ => Err(io::Error::new(io::ErrorKind::PermissionDenied, "no udp")),
+        };
+        let chain = SourceChain::new().with(SsSource::with_runner(runner));
+
+        let err = Snapshot::capture_from(&chain).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
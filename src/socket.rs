@@ -0,0 +1,264 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Transport protocol of a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        })
+    }
+}
+
+/// Address family of a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    /// Family of `addr`.
+    pub fn of(addr: &IpAddr) -> Family {
+        match addr {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+}
+
+/// Kernel socket state, using the names `ss` prints.
+///
+/// UDP sockets only ever report [`SocketState::Unconnected`] (bound, no peer)
+/// or [`SocketState::Established`] (connected).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SocketState {
+    Established,
+    SynSent,
+    SynRecv,
+    FinWait1,
+    FinWait2,
+    TimeWait,
+    Unconnected,
+    CloseWait,
+    LastAck,
+    Listen,
+    Closing,
+    /// A state code this crate does not know about.
+    Unknown(u8),
+}
+
+impl SocketState {
+    /// Decodes the numeric state used by procfs (`st`) and `inet_diag`.
+    pub fn from_kernel(code: u8) -> SocketState {
+        match code {
+            0x01 => SocketState::Established,
+            0x02 => SocketState::SynSent,
+            0x03 | 0x0C => SocketState::SynRecv,
+            0x04 => SocketState::FinWait1,
+            0x05 => SocketState::FinWait2,
+            0x06 => SocketState::TimeWait,
+            0x07 => SocketState::Unconnected,
+            0x08 => SocketState::CloseWait,
+            0x09 => SocketState::LastAck,
+            0x0A => SocketState::Listen,
+            0x0B => SocketState::Closing,
+            other => SocketState::Unknown(other),
+        }
+    }
+
+    /// Decodes the state column printed by `ss` (e.g. `LISTEN`, `UNCONN`).
+    pub fn from_ss(name: &str) -> Option<SocketState> {
+        Some(match name {
+            "ESTAB" => SocketState::Established,
+            "SYN-SENT" => SocketState::SynSent,
+            "SYN-RECV" => SocketState::SynRecv,
+            "FIN-WAIT-1" => SocketState::FinWait1,
+            "FIN-WAIT-2" => SocketState::FinWait2,
+            "TIME-WAIT" => SocketState::TimeWait,
+            "UNCONN" => SocketState::Unconnected,
+            "CLOSE-WAIT" => SocketState::CloseWait,
+            "LAST-ACK" => SocketState::LastAck,
+            "LISTEN" => SocketState::Listen,
+            "CLOSING" => SocketState::Closing,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for SocketState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocketState::Established => f.write_str("ESTAB"),
+            SocketState::SynSent => f.write_str("SYN-SENT"),
+            SocketState::SynRecv => f.write_str("SYN-RECV"),
+            SocketState::FinWait1 => f.write_str("FIN-WAIT-1"),
+            SocketState::FinWait2 => f.write_str("FIN-WAIT-2"),
+            SocketState::TimeWait => f.write_str("TIME-WAIT"),
+            SocketState::Unconnected => f.write_str("UNCONN"),
+            SocketState::CloseWait => f.write_str("CLOSE-WAIT"),
+            SocketState::LastAck => f.write_str("LAST-ACK"),
+            SocketState::Listen => f.write_str("LISTEN"),
+            SocketState::Closing => f.write_str("CLOSING"),
+            SocketState::Unknown(code) => write!(f, "UNKNOWN({:#04x})", code),
+        }
+    }
+}
+
+/// One socket as reported by a [`SocketSource`](crate::SocketSource).
+///
+/// `inode` and `uid` are only known to the procfs and netlink backends; `ss`
+/// without `-e` does not print them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Socket {
+    pub protocol: Protocol,
+    pub family: Family,
+    /// Local address the socket is bound to (`0.0.0.0` / `::` for wildcards).
+    pub local_addr: IpAddr,
+    /// Local port.
+    pub port: u16,
+    /// Peer address, or `None` for listening and unconnected sockets.
+    pub remote: Option<SocketAddr>,
+    pub state: SocketState,
+    /// Bytes in the receive queue (`Recv-Q`).
+    pub recv_q: u32,
+    /// Bytes in the send queue (`Send-Q`); the backlog for listeners.
+    pub send_q: u32,
+    /// Socket inode, as found under `/proc/<pid>/fd`.
+    pub inode: Option<u64>,
+    /// Effective UID of the socket owner.
+    pub uid: Option<u32>,
+    /// Whether an IPv6 socket is `IPV6_V6ONLY`, if the backend can tell.
+    ///
+    /// `ss` prints a dual-stack wildcard as `*` and a v6-only one as `[::]`;
+    /// netlink reports the flag directly; procfs cannot tell and reports
+    /// `None`. Always `None` for IPv4 sockets.
+    pub v6only: Option<bool>,
+    /// Interface the socket is bound to (`SO_BINDTODEVICE`), as in the
+    /// `%lo` suffix printed by `ss`. procfs does not expose it.
+    pub interface: Option<String>,
+}
+
+impl Socket {
+    /// Local address and port as a [`SocketAddr`].
+    pub fn local(&self) -> SocketAddr {
+        SocketAddr::new(self.local_addr, self.port)
+    }
+
+    /// Whether this socket stops a new socket of the same protocol from
+    /// binding `addr` on [`Socket::port`], following the kernel's rules
+    /// without `SO_REUSEADDR` / `SO_REUSEPORT`:
+    ///
+    ///   * within a family, a wildcard (`0.0.0.0` / `::`) conflicts with every
+    ///     address, and specific addresses only conflict with themselves;
+    ///   * an IPv6 wildcard conflicts with IPv4 addresses too, unless it is
+    ///     `IPV6_V6ONLY` (an unknown flag is treated as dual-stack);
+    ///   * IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) compare as IPv4.
+    ///
+    /// A candidate `::` is assumed to be dual-stack, as with the Linux default
+    /// `net.ipv6.bindv6only = 0`.
+    pub fn conflicts_with(&self, addr: IpAddr) -> bool {
+        let dual_stack = !self.v6only.unwrap_or(false);
+
+        match (canonical(self.local_addr), canonical(addr)) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => a.is_unspecified() || b.is_unspecified() || a == b,
+            (IpAddr::V6(a), IpAddr::V6(b)) => a.is_unspecified() || b.is_unspecified() || a == b,
+            (IpAddr::V6(a), IpAddr::V4(_)) => a.is_unspecified() && dual_stack,
+            (IpAddr::V4(_), IpAddr::V6(b)) => b.is_unspecified(),
+        }
+    }
+}
+
+/// Folds IPv4-mapped IPv6 addresses into plain IPv4 ones.
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_and_ss_states_agree() {
+        for code in 1..=0x0B {
+            let state = SocketState::from_kernel(code);
+            assert_eq!(SocketState::from_ss(&state.to_string()), Some(state));
+        }
+        assert_eq!(SocketState::from_kernel(0x0C), SocketState::SynRecv);
+        assert_eq!(SocketState::from_kernel(0x42), SocketState::Unknown(0x42));
+        assert_eq!(SocketState::from_ss("BOGUS"), None);
+    }
+
+    fn bound(addr: &str, v6only: Option<bool>) -> Socket {
+        let local_addr: IpAddr = addr.parse().unwrap();
+        Socket {
+            protocol: Protocol::Tcp,
+            family: Family::of(&local_addr),
+            local_addr,
+            port: 8080,
+            remote: None,
+            state: SocketState::Listen,
+            recv_q: 0,
+            send_q: 128,
+            inode: None,
+            uid: None,
+            v6only,
+            interface: None,
+        }
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_wildcard_and_specific_addresses() {
+        let loopback = bound("127.0.0.1", None);
+        assert!(loopback.conflicts_with(ip("127.0.0.1")));
+        assert!(loopback.conflicts_with(ip("0.0.0.0")));
+        assert!(!loopback.conflicts_with(ip("10.0.0.5")));
+        assert!(!loopback.conflicts_with(ip("::1")));
+
+        let any = bound("0.0.0.0", None);
+        assert!(any.conflicts_with(ip("10.0.0.5")));
+        assert!(!any.conflicts_with(ip("fe80::1")));
+        assert!(any.conflicts_with(ip("::")));
+    }
+
+    #[test]
+    fn ipv6_wildcard_respects_v6only() {
+        let dual = bound("::", Some(false));
+        assert!(dual.conflicts_with(ip("10.0.0.5")));
+        assert!(dual.conflicts_with(ip("fe80::1")));
+
+        let v6only = bound("::", Some(true));
+        assert!(!v6only.conflicts_with(ip("10.0.0.5")));
+        assert!(v6only.conflicts_with(ip("::1")));
+
+        let unknown = bound("::", None);
+        assert!(unknown.conflicts_with(ip("10.0.0.5")));
+
+        let specific = bound("2001:db8::1", None);
+        assert!(!specific.conflicts_with(ip("0.0.0.0")));
+        assert!(!specific.conflicts_with(ip("2001:db8::2")));
+        assert!(specific.conflicts_with(ip("::")));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_compare_as_ipv4() {
+        let mapped = bound("::ffff:10.0.0.5", None);
+        assert!(mapped.conflicts_with(ip("10.0.0.5")));
+        assert!(!mapped.conflicts_with(ip("10.0.0.6")));
+        assert!(bound("10.0.0.5", None).conflicts_with(ip("::ffff:10.0.0.5")));
+    }
+}
@@ -0,0 +1,428 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cancel::{CancelHandle, POLL_INTERVAL};
+use crate::error::WalledError;
+use crate::snapshot::sorted;
+use crate::socket::{Protocol, Socket};
+use crate::ss::{CommandRunner, SystemRunner};
+
+/// A backend able to enumerate the listening sockets of the host.
+///
+/// The crate ships [`ProcfsSource`], [`SsSource`] and, with the `netlink`
+/// feature, [`NetlinkSource`]. Implement this trait to plug in a source for a
+/// special environment (a remote agent, a recorded inventory, ...) and add it
+/// to a [`SourceChain`].
+pub trait SocketSource {
+    /// Short, stable name used to report which backend answered.
+    fn name(&self) -> &'static str;
+
+    /// Every listening socket for `protocol`, IPv4 and IPv6 combined.
+    ///
+    /// For UDP, "listening" means bound and unconnected, as with `ss -uln`.
+    fn listening_sockets(&self, protocol: Protocol) -> io::Result<Vec<Socket>>;
+}
+
+/// Reads `/proc/net/{tcp,udp}` and their IPv6 counterparts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcfsSource;
+
+impl SocketSource for ProcfsSource {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn listening_sockets(&self, protocol: Protocol) -> io::Result<Vec<Socket>> {
+        crate::procfs::listening_sockets(protocol)
+    }
+}
+
+/// Runs `ss -tlnH` / `ss -ulnH` and parses its output.
+///
+/// The process is spawned through a [`CommandRunner`]; [`SsSource::new`]
+/// uses the real binary, while [`SsSource::with_runner`] accepts a stand-in
+/// such as a closure returning recorded output.
+///
+/// Lines that cannot be parsed are skipped unless [`SsSource::strict`] is
+/// set, in which case the scan fails with a [`WalledError::Parse`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SsSource<R = SystemRunner> {
+    runner: R,
+    strict: bool,
+}
+
+impl SsSource {
+    /// Creates a source that spawns the real `ss` binary.
+    pub fn new() -> Self {
+        SsSource::with_runner(SystemRunner::new())
+    }
+}
+
+impl<R: CommandRunner> SsSource<R> {
+    /// Creates a source that runs `ss` through `runner`.
+    pub fn with_runner(runner: R) -> Self {
+        SsSource {
+            runner,
+            strict: false,
+        }
+    }
+
+    /// Fails the scan on the first line of `ss` output that cannot be parsed,
+    /// instead of skipping it.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+}
+
+impl<R: CommandRunner> SocketSource for SsSource<R> {
+    fn name(&self) -> &'static str {
+        "ss"
+    }
+
+    fn listening_sockets(&self, protocol: Protocol) -> io::Result<Vec<Socket>> {
+        crate::ss::listening_sockets(&self.runner, protocol, self.strict)
+    }
+}
+
+/// Asks the kernel directly over `NETLINK_SOCK_DIAG`.
+#[cfg(feature = "netlink")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetlinkSource;
+
+#[cfg(feature = "netlink")]
+impl SocketSource for NetlinkSource {
+    fn name(&self) -> &'static str {
+        "netlink"
+    }
+
+    fn listening_sockets(&self, protocol: Protocol) -> io::Result<Vec<Socket>> {
+        crate::netlink::listening_sockets(protocol)
+    }
+}
+
+/// Result of a [`SourceChain`] query, tagged with the backend that produced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceAnswer {
+    /// [`SocketSource::name`] of the backend that answered.
+    pub source: &'static str,
+    /// Listening sockets as reported by that backend.
+    pub sockets: Vec<Socket>,
+}
+
+impl SourceAnswer {
+    /// Ports of [`SourceAnswer::sockets`], sorted and deduplicated.
+    pub fn ports(&self) -> Vec<u16> {
+        sorted(self.sockets.iter().map(|socket| socket.port).collect())
+    }
+}
+
+/// An ordered list of [`SocketSource`]s, tried one after another until one
+/// of them answers.
+///
+/// [`SourceChain::default`] tries `netlink` (when the feature is enabled),
+/// then `procfs`, then `ss`, which is the chain every port function of this
+/// crate uses.
+///
+/// A chain waits for its backends as long as they take, unless it is given a
+/// [`SourceChain::with_timeout`] or a [`SourceChain::with_cancel`]. Each
+/// backend then runs on a helper thread which the chain stops waiting for
+/// once the time is up or the handle is cancelled. A backend that cannot be
+/// interrupted, such as a procfs read stuck on a hung mount, is abandoned
+/// and finishes in the background; to also kill a stuck `ss` child, give
+/// its [`SystemRunner`] the same limits.
+///
+/// Clones share the backends, so a chain can be handed to another thread,
+/// e.g. through [`PortQuery::spawn_from`](crate::PortQuery::spawn_from).
+#[derive(Clone)]
+pub struct SourceChain {
+    sources: Vec<Arc<dyn SocketSource + Send + Sync>>,
+    timeout: Option<Duration>,
+    cancel: Option<CancelHandle>,
+}
+
+impl SourceChain {
+    /// Creates an empty chain; add backends with [`SourceChain::with`].
+    pub fn new() -> Self {
+        SourceChain {
+            sources: Vec::new(),
+            timeout: None,
+            cancel: None,
+        }
+    }
+
+    /// Appends `source` to the end of the chain.
+    pub fn with(mut self, source: impl SocketSource + Send + Sync + 'static) -> Self {
+        self.sources.push(Arc::new(source));
+        self
+    }
+
+    /// Gives up with a [`WalledError::TimedOut`] when no backend has
+    /// answered within `timeout`. The budget covers a whole
+    /// [`SourceChain::listening_sockets`] call, fallbacks included.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Gives up with a [`WalledError::Cancelled`] once `handle` is cancelled.
+    pub fn with_cancel(mut self, handle: CancelHandle) -> Self {
+        self.cancel = Some(handle);
+        self
+    }
+
+    /// Names of the backends, in the order they are tried.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.sources.iter().map(|source| source.name()).collect()
+    }
+
+    /// Queries each backend in order and returns the first successful answer.
+    ///
+    /// If every backend fails, the returned error is a
+    /// [`WalledError::NoSourceAnswered`] holding each backend's failure, with
+    /// the kind of the last one. Running out of time or being cancelled
+    /// fails straight away with [`WalledError::TimedOut`] or
+    /// [`WalledError::Cancelled`], naming the backend that was running.
+    pub fn listening_sockets(&self, protocol: Protocol) -> io::Result<SourceAnswer> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut failures = Vec::new();
+
+        for source in &self.sources {
+            let result = if deadline.is_none() && self.cancel.is_none() {
+                source.listening_sockets(protocol)
+            } else {
+                self.bounded(source, protocol, deadline)?
+            };
+
+            match result {
+                Ok(sockets) => {
+                    return Ok(SourceAnswer {
+                        source: source.name(),
+                        sockets,
+                    });
+                }
+                Err(e) => failures.push((source.name(), e)),
+            }
+        }
+
+        Err(WalledError::NoSourceAnswered { failures }.into())
+    }
+
+    /// Runs `source` on a helper thread and waits for it until `deadline`
+    /// or cancellation. The outer error is the chain giving up; the inner
+    /// result is the backend's own answer.
+    fn bounded(
+        &self,
+        source: &Arc<dyn SocketSource + Send + Sync>,
+        protocol: Protocol,
+        deadline: Option<Instant>,
+    ) -> io::Result<io::Result<Vec<Socket>>> {
+        let name = source.name();
+        let (tx, rx) = mpsc::channel();
+        let worker = Arc::clone(source);
+        thread::Builder::new()
+            .name(format!("walled-{}", name))
+            .spawn(move || {
+                // The receiver is gone if the chain gave up in the meantime.
+                let _ = tx.send(worker.listening_sockets(protocol));
+            })?;
+
+        loop {
+            if self.cancel.as_ref().is_some_and(CancelHandle::is_cancelled) {
+                return Err(WalledError::Cancelled {
+                    backend: name.to_string(),
+                }
+                .into());
+            }
+
+            let wait = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(WalledError::TimedOut {
+                            backend: name.to_string(),
+                            timeout: self.timeout.unwrap_or_default(),
+                        }
+                        .into());
+                    }
+                    left.min(POLL_INTERVAL)
+                }
+                None => POLL_INTERVAL,
+            };
+
+            match rx.recv_timeout(wait) {
+                Ok(result) => return Ok(result),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Ok(Err(io::Error::other(format!("{} panicked", name))));
+                }
+            }
+        }
+    }
+}
+
+impl Default for SourceChain {
+    fn default() -> Self {
+        let chain = SourceChain::new();
+        #[cfg(feature = "netlink")]
+        let chain = chain.with(NetlinkSource);
+        chain.with(ProcfsSource).with(SsSource::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::listener;
+
+    struct Fixed(&'static str, io::Result<Vec<u16>>);
+
+    impl SocketSource for Fixed {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn listening_sockets(&self, protocol: Protocol) -> io::Result<Vec<Socket>> {
+            match &self.1 {
+                Ok(ports) => Ok(ports.iter().map(|&port| listener(protocol, port)).collect()),
+                Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+            }
+        }
+    }
+
+    fn missing() -> io::Result<Vec<u16>> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "not here"))
+    }
+
+    #[test]
+    fn first_answering_source_wins() {
+        let chain = SourceChain::new()
+            .with(Fixed("broken", missing()))
+            .with(Fixed("second", Ok(vec![80, 22, 80])))
+            .with(Fixed("third", Ok(vec![443])));
+
+        let answer = chain.listening_sockets(Protocol::Tcp).unwrap();
+        assert_eq!(answer.source, "second");
+        assert_eq!(answer.sockets.len(), 3);
+        assert_eq!(answer.ports(), vec![22, 80]);
+    }
+
+    #[test]
+    fn all_failures_are_reported() {
+        let chain = SourceChain::new().with(Fixed("a", missing())).with(Fixed(
+            "b",
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied")),
+        ));
+
+        let err = chain.listening_sockets(Protocol::Udp).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("a: not here"));
+        assert!(err.to_string().contains("b: denied"));
+
+        let Some(WalledError::NoSourceAnswered { failures }) = WalledError::of(&err) else {
+            panic!("unexpected error {:?}", err);
+        };
+        let names: Vec<_> = failures.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    struct Stuck;
+
+    impl SocketSource for Stuck {
+        fn name(&self) -> &'static str {
+            "stuck"
+        }
+
+        fn listening_sockets(&self, _: Protocol) -> io::Result<Vec<Socket>> {
+            thread::sleep(Duration::from_secs(5));
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn timeout_abandons_stuck_sources() {
+        let chain = SourceChain::new()
+            .with(Fixed("broken", missing()))
+            .with(Stuck)
+            .with(Fixed("never", Ok(vec![22])))
+            .with_timeout(Duration::from_millis(100));
+
+        let started = Instant::now();
+        let err = chain.listening_sockets(Protocol::Tcp).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::TimedOut { backend, .. }) if backend == "stuck"
+        ));
+
+        let quick = SourceChain::new()
+            .with(Fixed("broken", missing()))
+            .with(Fixed("second", Ok(vec![22])))
+            .with_timeout(Duration::from_secs(10));
+        assert_eq!(
+            quick.listening_sockets(Protocol::Tcp).unwrap().source,
+            "second"
+        );
+    }
+
+    #[test]
+    fn cancellation_stops_the_chain() {
+        let handle = CancelHandle::new();
+        let chain = SourceChain::new().with(Stuck).with_cancel(handle.clone());
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.cancel();
+        });
+
+        let started = Instant::now();
+        let err = chain.listening_sockets(Protocol::Udp).unwrap_err();
+        canceller.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::Cancelled { backend }) if backend == "stuck"
+        ));
+    }
+
+    #[test]
+    fn ss_source_uses_injected_runner() {
+        let runner = |_: &str, _: &[&str]| {
+            Ok("LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\nLISTEN 0 128 [::]:22 [::]:*\n".to_string())
+        };
+        let chain = SourceChain::new()
+            .with(Fixed("broken", missing()))
+            .with(SsSource::with_runner(runner));
+
+        let answer = chain.listening_sockets(Protocol::Tcp).unwrap();
+        assert_eq!(answer.source, "ss");
+        assert_eq!(answer.sockets.len(), 2);
+        assert_eq!(answer.ports(), vec![22]);
+    }
+
+    #[test]
+    fn default_chain_order() {
+        let names = SourceChain::default().names();
+        assert_eq!(&names[names.len() - 2..], &["procfs", "ss"]);
+    }
+
+    #[test]
+    fn empty_chain_answers_nothing() {
+        let err = SourceChain::new()
+            .listening_sockets(Protocol::Tcp)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::NoSourceAnswered { failures }) if failures.is_empty()
+        ));
+        assert_eq!(
+            err.to_string(),
+            "no socket source answered (the chain is empty)"
+        );
+    }
+}
@@ -0,0 +1,537 @@
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::cancel::{CancelHandle, POLL_INTERVAL};
+use crate::endpoint::{SsDialect, SsEndpoint, SsHost, parse_ss_endpoint};
+use crate::error::WalledError;
+use crate::socket::{Family, Protocol, Socket, SocketState};
+
+/// Runs an external program on behalf of [`SsSource`](crate::SsSource).
+///
+/// The default [`SystemRunner`] spawns the real binary. Tests and special
+/// environments can substitute recorded output instead; any
+/// `Fn(&str, &[&str]) -> io::Result<String>` closure is a runner too.
+pub trait CommandRunner {
+    /// Runs `program` with `args` and returns its standard output.
+    ///
+    /// A non-zero exit status must be reported as an error, ideally a
+    /// [`WalledError::BackendFailed`] carrying the captured stderr.
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<String>;
+}
+
+/// Spawns the program with [`std::process::Command`], without a shell.
+///
+/// A program that cannot be found is reported as
+/// [`WalledError::BackendNotFound`], one that may not be executed as
+/// [`WalledError::PermissionDenied`], and a non-zero exit as
+/// [`WalledError::BackendFailed`] with the program's stderr.
+///
+/// By default the runner waits for the program however long it takes. With
+/// [`SystemRunner::with_timeout`] or [`SystemRunner::with_cancel`] the child
+/// is killed once the time is up or the handle is cancelled, and the run
+/// fails with [`WalledError::TimedOut`] or [`WalledError::Cancelled`].
+#[derive(Debug, Clone, Default)]
+pub struct SystemRunner {
+    timeout: Option<Duration>,
+    cancel: Option<CancelHandle>,
+}
+
+impl SystemRunner {
+    /// A runner without a time limit.
+    pub fn new() -> SystemRunner {
+        SystemRunner::default()
+    }
+
+    /// Kills the program if it has not exited after `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> SystemRunner {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Kills the program when `handle` is cancelled.
+    pub fn with_cancel(mut self, handle: CancelHandle) -> SystemRunner {
+        self.cancel = Some(handle);
+        self
+    }
+
+    /// Waits for `child` to exit. With a timeout or a cancel handle the
+    /// child is polled instead, and killed and reaped once either fires.
+    fn wait(&self, child: &mut Child, program: &str) -> io::Result<ExitStatus> {
+        if self.timeout.is_none() && self.cancel.is_none() {
+            return child.wait();
+        }
+
+        let started = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+
+            let error = if self.cancel.as_ref().is_some_and(CancelHandle::is_cancelled) {
+                WalledError::Cancelled {
+                    backend: program.to_string(),
+                }
+            } else if let Some(timeout) = self.timeout
+                && started.elapsed() >= timeout
+            {
+                WalledError::TimedOut {
+                    backend: program.to_string(),
+                    timeout,
+                }
+            } else {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            };
+
+            // The child may have exited since `try_wait`; either way it is
+            // reaped so no zombie is left behind.
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(error.into());
+        }
+    }
+}
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<String> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| spawn_error(e, program))?;
+
+        // Both pipes are drained while we wait, so a chatty child never
+        // blocks on a full pipe.
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+        let status = self.wait(&mut child, program)?;
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+
+        if !status.success() {
+            return Err(WalledError::BackendFailed {
+                backend: program.to_string(),
+                status: status.code(),
+                stderr: String::from_utf8_lossy(&stderr).trim().to_string(),
+            }
+            .into());
+        }
+
+        Ok(String::from_utf8_lossy(&stdout).into_owned())
+    }
+}
+
+/// Reads `pipe` to the end on a helper thread.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+impl<F> CommandRunner for F
+where
+    F: Fn(&str, &[&str]) -> io::Result<String>,
+{
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<String> {
+        self(program, args)
+    }
+}
+
+/// A line of `ss` output that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SkippedLine {
+    /// 1-based line number within the output.
+    pub line_no: usize,
+    pub line: String,
+}
+
+/// `ss` output parsed leniently: the sockets that could be decoded, plus
+/// every line that could not.
+///
+/// A non-empty [`SsOutput::skipped`] usually means the installed iproute2
+/// prints a format this crate does not know, in which case the socket list
+/// is incomplete and ports may wrongly look free.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsOutput {
+    pub sockets: Vec<Socket>,
+    pub skipped: Vec<SkippedLine>,
+}
+
+impl SsOutput {
+    /// The sockets, or a [`WalledError::Parse`] for the first skipped line.
+    pub fn into_strict(self) -> io::Result<Vec<Socket>> {
+        match self.skipped.into_iter().next() {
+            Some(SkippedLine { line_no, line }) => Err(WalledError::Parse { line_no, line }.into()),
+            None => Ok(self.sockets),
+        }
+    }
+}
+
+/// Parses the output of `ss -tlnH` or `ss -ulnH` into [`Socket`]s.
+///
+/// Columns are `State Recv-Q Send-Q Local Peer`; the address columns are
+/// decoded with [`parse_ss_endpoint`], reading `*` by the [`SsDialect`] of
+/// the whole output. Lines with fewer columns, an unknown
+/// state or an unparseable local address are skipped. Sockets are
+/// returned in the order they appear; `ss` does not print `inode` or `uid`
+/// without `-e`, so those are `None`.
+///
+/// Use [`parse_ss_lines`] to see the skipped lines, or
+/// [`parse_ss_output_strict`] to fail on them.
+pub fn parse_ss_output(output: &str, protocol: Protocol) -> Vec<Socket> {
+    parse_ss_lines(output, protocol).sockets
+}
+
+/// Like [`parse_ss_output`], but fails with a [`WalledError::Parse`] naming
+/// the first line that could not be parsed, so a format change cannot make
+/// used ports look free.
+pub fn parse_ss_output_strict(output: &str, protocol: Protocol) -> io::Result<Vec<Socket>> {
+    parse_ss_lines(output, protocol).into_strict()
+}
+
+/// Like [`parse_ss_output`], but returns the lines that could not be parsed
+/// next to the sockets. Blank lines and a `State ...` header are not
+/// reported.
+pub fn parse_ss_lines(output: &str, protocol: Protocol) -> SsOutput {
+    let dialect = SsDialect::detect(output);
+    let mut sockets = Vec::new();
+    let mut skipped = Vec::new();
+
+    for (index, line) in output.lines().enumerate() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() || parts[0] == "State" {
+            continue;
+        }
+        if parts.len() >= 4
+            && let Some(state) = SocketState::from_ss(parts[0])
+            && let Ok(recv_q) = parts[1].parse::<u32>()
+            && let Ok(send_q) = parts[2].parse::<u32>()
+            && let Some(local) = parse_ss_endpoint(parts[3])
+            && let Some(port) = local.port
+        {
+            let local_addr = local.host.ip(dialect);
+            let remote = parts
+                .get(4)
+                .and_then(|peer| parse_ss_endpoint(peer))
+                .and_then(|peer| Some(SocketAddr::new(peer.host.ip(dialect), peer.port?)));
+
+            sockets.push(Socket {
+                protocol,
+                family: Family::of(&local_addr),
+                local_addr,
+                port,
+                remote,
+                state,
+                recv_q,
+                send_q,
+                inode: None,
+                uid: None,
+                v6only: wildcard_v6only(parts[3], &local, dialect),
+                interface: local.scope,
+            });
+        } else {
+            skipped.push(SkippedLine {
+                line_no: index + 1,
+                line: line.to_string(),
+            });
+        }
+    }
+
+    SsOutput { sockets, skipped }
+}
+
+/// Every listening socket for `protocol` as reported by `ss -lnH`.
+///
+/// With `strict`, an unparseable line fails the scan instead of being skipped.
+pub(crate) fn listening_sockets(
+    runner: &dyn CommandRunner,
+    protocol: Protocol,
+    strict: bool,
+) -> io::Result<Vec<Socket>> {
+    let flags = match protocol {
+        Protocol::Tcp => "-tlnH",
+        Protocol::Udp => "-ulnH",
+    };
+
+    let stdout = runner.run("ss", &[flags])?;
+    let output = parse_ss_lines(&stdout, protocol);
+    if strict {
+        output.into_strict()
+    } else {
+        Ok(output.sockets)
+    }
+}
+
+/// Classifies a failure to start `program`.
+fn spawn_error(e: io::Error, program: &str) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::NotFound => WalledError::BackendNotFound {
+            backend: program.to_string(),
+        }
+        .into(),
+        io::ErrorKind::PermissionDenied => WalledError::PermissionDenied {
+            operation: format!("execute `{}`", program),
+        }
+        .into(),
+        _ => e,
+    }
+}
+
+/// Current `ss` prints the IPv6 wildcard as `*` for dual-stack sockets and
+/// as `[::]` for `IPV6_V6ONLY` ones; any other address, and the legacy
+/// dialect, do not reveal the flag.
+fn wildcard_v6only(column: &str, local: &SsEndpoint, dialect: SsDialect) -> Option<bool> {
+    if dialect == SsDialect::Legacy {
+        return None;
+    }
+    match local.host {
+        SsHost::Any => Some(false),
+        SsHost::Ip(IpAddr::V6(addr)) if addr.is_unspecified() && column.starts_with('[') => {
+            Some(true)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    const TCP: &str = include_str!("../tests/fixtures/ss/tcp.txt");
+    const UDP: &str = include_str!("../tests/fixtures/ss/udp.txt");
+    const MALFORMED: &str = include_str!("../tests/fixtures/ss/malformed.txt");
+    const TCP_LEGACY: &str = include_str!("../tests/fixtures/ss/tcp-legacy.txt");
+
+    fn ports(sockets: &[Socket]) -> Vec<u16> {
+        sockets.iter().map(|socket| socket.port).collect()
+    }
+
+    #[test]
+    fn parses_tcp_fixture() {
+        let sockets = parse_ss_output(TCP, Protocol::Tcp);
+        assert_eq!(ports(&sockets), vec![53, 22, 6379, 22, 6379, 9100]);
+
+        assert_eq!(sockets[0].local_addr, IpAddr::from([127, 0, 0, 53]));
+        assert_eq!(sockets[0].state, SocketState::Listen);
+        assert_eq!((sockets[0].recv_q, sockets[0].send_q), (0, 4096));
+        assert_eq!(sockets[0].remote, None);
+        assert_eq!(sockets[3].local_addr, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        assert_eq!(sockets[4].local_addr, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(sockets[4].family, Family::V6);
+        assert_eq!(sockets[5].local_addr, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+
+        assert_eq!(sockets[1].v6only, None);
+        assert_eq!(sockets[3].v6only, Some(true));
+        assert_eq!(sockets[4].v6only, None);
+        assert_eq!(sockets[5].v6only, Some(false));
+    }
+
+    #[test]
+    fn parses_legacy_tcp_fixture() {
+        let output = parse_ss_lines(TCP_LEGACY, Protocol::Tcp);
+        assert!(output.skipped.is_empty());
+        let sockets = output.sockets;
+        assert_eq!(ports(&sockets), vec![22, 25, 22, 25]);
+
+        // Older iproute2 prints `0.0.0.0` as `*`.
+        assert_eq!(sockets[0].local_addr, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(sockets[0].family, Family::V4);
+        assert_eq!(sockets[0].v6only, None);
+        assert!(!sockets[0].conflicts_with(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert!(sockets[0].conflicts_with(IpAddr::from([10, 0, 0, 5])));
+
+        assert_eq!(sockets[2].local_addr, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        assert_eq!(sockets[3].local_addr, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert!(sockets.iter().all(|socket| socket.remote.is_none()));
+    }
+
+    #[test]
+    fn parses_udp_fixture_with_scopes_and_wildcards() {
+        let sockets = parse_ss_output(UDP, Protocol::Udp);
+        assert_eq!(ports(&sockets), vec![53, 68, 123, 546, 5353]);
+        assert!(sockets.iter().all(|s| s.state == SocketState::Unconnected));
+        assert_eq!(sockets[0].interface.as_deref(), Some("lo"));
+        assert_eq!(sockets[1].local_addr, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(sockets[1].interface.as_deref(), Some("eth0"));
+        assert_eq!(sockets[2].interface, None);
+        assert_eq!(
+            sockets[3].local_addr,
+            "fe80::5054:ff:fe12:3456".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(sockets[3].interface.as_deref(), Some("eth0"));
+    }
+
+    #[test]
+    fn parses_connected_peer() {
+        let sockets = parse_ss_output("ESTAB 0 36 10.0.0.5:22 10.0.0.9:51234", Protocol::Tcp);
+        assert_eq!(sockets[0].remote, Some("10.0.0.9:51234".parse().unwrap()));
+    }
+
+    #[test]
+    fn skips_malformed_lines() {
+        assert_eq!(
+            ports(&parse_ss_output(MALFORMED, Protocol::Tcp)),
+            vec![8080]
+        );
+    }
+
+    #[test]
+    fn lenient_mode_reports_skipped_lines() {
+        let output = parse_ss_lines(MALFORMED, Protocol::Tcp);
+        assert_eq!(ports(&output.sockets), vec![8080]);
+
+        let skipped: Vec<usize> = output.skipped.iter().map(|s| s.line_no).collect();
+        assert_eq!(skipped, vec![1, 3, 4, 5]);
+        assert_eq!(output.skipped[1].line, "this line is garbage");
+
+        let clean = parse_ss_lines(
+            &format!("State Recv-Q Send-Q Local Peer\n{}", TCP),
+            Protocol::Tcp,
+        );
+        assert!(clean.skipped.is_empty());
+    }
+
+    #[test]
+    fn strict_mode_fails_on_the_first_bad_line() {
+        assert_eq!(parse_ss_output_strict(UDP, Protocol::Udp).unwrap().len(), 5);
+
+        let err = parse_ss_output_strict(MALFORMED, Protocol::Tcp).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        match WalledError::of(&err) {
+            Some(WalledError::Parse { line_no, line }) => {
+                assert_eq!(*line_no, 1);
+                assert_eq!(line, "LISTEN 0 128");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        let runner = |_: &str, _: &[&str]| Ok(MALFORMED.to_string());
+        assert_eq!(
+            listening_sockets(&runner, Protocol::Tcp, false)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(listening_sockets(&runner, Protocol::Tcp, true).is_err());
+    }
+
+    #[test]
+    fn empty_output_has_no_sockets() {
+        assert!(parse_ss_output("", Protocol::Tcp).is_empty());
+    }
+
+    #[test]
+    fn runner_receives_protocol_flags() {
+        let runner = |program: &str, args: &[&str]| {
+            assert_eq!(program, "ss");
+            Ok(match args {
+                ["-tlnH"] => TCP.to_string(),
+                ["-ulnH"] => UDP.to_string(),
+                _ => panic!("unexpected arguments {:?}", args),
+            })
+        };
+
+        let tcp = listening_sockets(&runner, Protocol::Tcp, false).unwrap();
+        assert!(tcp.iter().all(|s| s.protocol == Protocol::Tcp));
+        assert_eq!(tcp.len(), 6);
+
+        let udp = listening_sockets(&runner, Protocol::Udp, false).unwrap();
+        assert!(udp.iter().any(|s| s.port == 546));
+    }
+
+    #[test]
+    fn system_runner_reports_missing_programs() {
+        let err = SystemRunner::new()
+            .run("walled-no-such-program", &[])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::BackendNotFound { backend }) if backend == "walled-no-such-program"
+        ));
+    }
+
+    #[test]
+    fn system_runner_captures_stderr() {
+        let err = SystemRunner::new()
+            .run(
+                "sh",
+                &["-c", "echo 'Cannot open netlink socket' >&2; exit 3"],
+            )
+            .unwrap_err();
+        match WalledError::of(&err) {
+            Some(WalledError::BackendFailed {
+                backend,
+                status,
+                stderr,
+            }) => {
+                assert_eq!(backend, "sh");
+                assert_eq!(*status, Some(3));
+                assert_eq!(stderr, "Cannot open netlink socket");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn system_runner_kills_on_timeout() {
+        let started = Instant::now();
+        let err = SystemRunner::new()
+            .with_timeout(Duration::from_millis(100))
+            .run("sleep", &["5"])
+            .unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::TimedOut { backend, .. }) if backend == "sleep"
+        ));
+
+        let out = SystemRunner::new()
+            .with_timeout(Duration::from_secs(10))
+            .run("sh", &["-c", "echo LISTEN"])
+            .unwrap();
+        assert_eq!(out, "LISTEN\n");
+    }
+
+    #[test]
+    fn system_runner_kills_on_cancel() {
+        let handle = CancelHandle::new();
+        let runner = SystemRunner::new().with_cancel(handle.clone());
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.cancel();
+        });
+
+        let started = Instant::now();
+        let err = runner.run("sleep", &["5"]).unwrap_err();
+        canceller.join().unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::Cancelled { backend }) if backend == "sleep"
+        ));
+    }
+
+    #[test]
+    fn runner_errors_are_propagated() {
+        let runner = |_: &str, _: &[&str]| -> io::Result<String> {
+            Err(io::Error::new(io::ErrorKind::NotFound, "no ss here"))
+        };
+        let err = listening_sockets(&runner, Protocol::Tcp, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,526 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::error::WalledError;
+use crate::ranges::PortRanges;
+
+/// The classic IANA boundary: ports below it are "well known" and, on a
+/// kernel with default settings, need `CAP_NET_BIND_SERVICE` to bind.
+pub const IANA_UNPRIVILEGED_PORT_START: u16 = 1024;
+
+/// The kernel's default `net.ipv4.ip_local_port_range`.
+pub const DEFAULT_LOCAL_PORT_RANGE: RangeInclusive<u16> = 32768..=60999;
+
+const UNPRIVILEGED_PORT_START: &str = "sys/net/ipv4/ip_unprivileged_port_start";
+const LOCAL_PORT_RANGE: &str = "sys/net/ipv4/ip_local_port_range";
+const LOCAL_RESERVED_PORTS: &str = "sys/net/ipv4/ip_local_reserved_ports";
+
+/// Where a port falls in the host's port layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PortClass {
+    /// Below `ip_unprivileged_port_start`; binding needs `CAP_NET_BIND_SERVICE`.
+    Privileged,
+    /// Listed in `ip_local_reserved_ports`: the kernel never hands it out as
+    /// an ephemeral port, so it is kept for a specific service.
+    Reserved,
+    /// Unprivileged and below the ephemeral range: safe to pick for a service.
+    Registered,
+    /// Inside `ip_local_port_range`, where the kernel picks source ports for
+    /// outbound connections and unbound sockets.
+    Ephemeral,
+    /// Unprivileged and above the ephemeral range.
+    AboveRange,
+}
+
+impl fmt::Display for PortClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PortClass::Privileged => "privileged",
+            PortClass::Reserved => "reserved",
+            PortClass::Registered => "registered",
+            PortClass::Ephemeral => "ephemeral",
+            PortClass::AboveRange => "above-range",
+        })
+    }
+}
+
+/// The privileged boundary, ephemeral range and reserved ports of a host, as
+/// set by `net.ipv4.ip_unprivileged_port_start`, `net.ipv4.ip_local_port_range`
+/// and `net.ipv4.ip_local_reserved_ports`.
+///
+/// [`PortLayout::default`] is the kernel default: privileged below 1024,
+/// ephemeral 32768‑60999, nothing reserved.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PortLayout {
+    unprivileged_port_start: u16,
+    ephemeral: RangeInclusive<u16>,
+    reserved: PortRanges,
+}
+
+impl PortLayout {
+    /// A layout with the given privileged boundary and ephemeral range.
+    pub fn new(unprivileged_port_start: u16, ephemeral: RangeInclusive<u16>) -> PortLayout {
+        PortLayout {
+            unprivileged_port_start,
+            ephemeral,
+            reserved: PortRanges::new(),
+        }
+    }
+
+    /// Reads the three sysctls of the running kernel.
+    pub fn current() -> io::Result<PortLayout> {
+        Sysctls::new().layout()
+    }
+
+    /// Uses `start` as the first unprivileged port, keeping the ephemeral
+    /// range and the reserved ports.
+    pub fn with_unprivileged_port_start(mut self, start: u16) -> PortLayout {
+        self.unprivileged_port_start = start;
+        self
+    }
+
+    /// Uses `reserved` as the administrator-reserved ports.
+    pub fn with_reserved(mut self, reserved: PortRanges) -> PortLayout {
+        self.reserved = reserved;
+        self
+    }
+
+    /// First port an unprivileged process may bind.
+    pub fn unprivileged_port_start(&self) -> u16 {
+        self.unprivileged_port_start
+    }
+
+    /// The kernel's ephemeral port range.
+    pub fn ephemeral(&self) -> RangeInclusive<u16> {
+        self.ephemeral.clone()
+    }
+
+    /// Ports listed in `ip_local_reserved_ports`.
+    pub fn reserved(&self) -> &PortRanges {
+        &self.reserved
+    }
+
+    /// Classifies `port`. Privileged wins when classes overlap, since such a
+    /// port still cannot be bound without the capability; a reserved port is
+    /// never handed out as ephemeral, so reserved wins over ephemeral.
+    pub fn classify(&self, port: u16) -> PortClass {
+        if port < self.unprivileged_port_start {
+            PortClass::Privileged
+        } else if self.reserved.contains(port) {
+            PortClass::Reserved
+        } else if self.ephemeral.contains(&port) {
+            PortClass::Ephemeral
+        } else if port < *self.ephemeral.start() {
+            PortClass::Registered
+        } else {
+            PortClass::AboveRange
+        }
+    }
+
+    /// Whether `port` lies in the ephemeral range.
+    pub fn is_ephemeral(&self, port: u16) -> bool {
+        self.ephemeral.contains(&port)
+    }
+
+    pub(crate) fn privileged_range(&self) -> RangeInclusive<u16> {
+        privileged_range(self.unprivileged_port_start)
+    }
+
+    pub(crate) fn unprivileged_range(&self) -> RangeInclusive<u16> {
+        unprivileged_range(self.unprivileged_port_start)
+    }
+
+    /// Whether `port` is listed in `ip_local_reserved_ports`.
+    pub fn is_reserved(&self, port: u16) -> bool {
+        self.reserved.contains(port)
+    }
+
+    /// Drops the ephemeral ports from a port list; `None` if nothing is left.
+    pub(crate) fn without_ephemeral(&self, ports: Option<Vec<u16>>) -> Option<Vec<u16>> {
+        without(ports, |port| self.is_ephemeral(port))
+    }
+
+    /// Drops the reserved ports from a port list; `None` if nothing is left.
+    pub(crate) fn without_reserved(&self, ports: Option<Vec<u16>>) -> Option<Vec<u16>> {
+        without(ports, |port| self.is_reserved(port))
+    }
+}
+
+impl Default for PortLayout {
+    fn default() -> PortLayout {
+        PortLayout::new(IANA_UNPRIVILEGED_PORT_START, DEFAULT_LOCAL_PORT_RANGE)
+    }
+}
+
+/// The port sysctls under a procfs mount, `/proc` unless configured
+/// otherwise.
+///
+/// A different root lets the readers and the `ip_local_reserved_ports`
+/// writer work on a container's procfs or on a plain directory in tests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sysctls {
+    root: PathBuf,
+}
+
+impl Sysctls {
+    /// The sysctls of the running kernel, under `/proc`.
+    pub fn new() -> Sysctls {
+        Sysctls::with_root("/proc")
+    }
+
+    /// The sysctls under the procfs mounted at `root`.
+    pub fn with_root(root: impl Into<PathBuf>) -> Sysctls {
+        Sysctls { root: root.into() }
+    }
+
+    /// The procfs root the sysctls are read from.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Reads `net.ipv4.ip_unprivileged_port_start`; see
+    /// [`unprivileged_port_start`].
+    pub fn unprivileged_port_start(&self) -> io::Result<u16> {
+        match self.read(UNPRIVILEGED_PORT_START)? {
+            Some(contents) => parse_port(&contents),
+            None => Ok(IANA_UNPRIVILEGED_PORT_START),
+        }
+    }
+
+    /// Reads `net.ipv4.ip_local_port_range`; see [`local_port_range`].
+    pub fn local_port_range(&self) -> io::Result<RangeInclusive<u16>> {
+        match self.read(LOCAL_PORT_RANGE)? {
+            Some(contents) => parse_port_range(&contents),
+            None => Ok(DEFAULT_LOCAL_PORT_RANGE),
+        }
+    }
+
+    /// Reads `net.ipv4.ip_local_reserved_ports`; see [`reserved_ports`].
+    pub fn reserved_ports(&self) -> io::Result<PortRanges> {
+        match self.read(LOCAL_RESERVED_PORTS)? {
+            Some(contents) => contents.parse(),
+            None => Ok(PortRanges::new()),
+        }
+    }
+
+    /// Reads all three sysctls into a [`PortLayout`].
+    pub fn layout(&self) -> io::Result<PortLayout> {
+        Ok(
+            PortLayout::new(self.unprivileged_port_start()?, self.local_port_range()?)
+                .with_reserved(self.reserved_ports()?),
+        )
+    }
+
+    /// Replaces `net.ipv4.ip_local_reserved_ports` with `ports`.
+    ///
+    /// Writing the real sysctl needs `CAP_NET_ADMIN` in the owning network
+    /// namespace; without it the kernel answers `PermissionDenied`.
+    pub fn set_reserved_ports(&self, ports: &PortRanges) -> io::Result<()> {
+        fs::write(self.path(LOCAL_RESERVED_PORTS), format!("{}\n", ports))
+            .map_err(|e| denied(e, || format!("write {}", sysctl_name(LOCAL_RESERVED_PORTS))))
+    }
+
+    /// Adds `range` to `net.ipv4.ip_local_reserved_ports`, keeping every
+    /// existing entry, and returns the merged list that was written.
+    pub fn reserve(&self, range: RangeInclusive<u16>) -> io::Result<PortRanges> {
+        let mut ports = self.reserved_ports()?;
+        ports.insert(range);
+        self.set_reserved_ports(&ports)?;
+        Ok(ports)
+    }
+
+    /// Removes `range` from `net.ipv4.ip_local_reserved_ports`, splitting
+    /// entries that only partly overlap it, and returns the list that was
+    /// written.
+    pub fn unreserve(&self, range: RangeInclusive<u16>) -> io::Result<PortRanges> {
+        let mut ports = self.reserved_ports()?;
+        ports.remove(range);
+        self.set_reserved_ports(&ports)?;
+        Ok(ports)
+    }
+
+    fn path(&self, sysctl: &str) -> PathBuf {
+        self.root.join(sysctl)
+    }
+
+    /// Contents of `sysctl`, or `None` if this kernel does not have it.
+    fn read(&self, sysctl: &str) -> io::Result<Option<String>> {
+        match fs::read_to_string(self.path(sysctl)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(denied(e, || format!("read {}", sysctl_name(sysctl)))),
+        }
+    }
+}
+
+impl Default for Sysctls {
+    fn default() -> Sysctls {
+        Sysctls::new()
+    }
+}
+
+/// Reads `net.ipv4.ip_unprivileged_port_start`, the first port an
+/// unprivileged process may bind.
+///
+/// Kernels older than 4.11 do not have the sysctl; they always use 1024, so
+/// [`IANA_UNPRIVILEGED_PORT_START`] is returned when the file is missing.
+/// Rootless container setups often lower it, down to 0.
+pub fn unprivileged_port_start() -> io::Result<u16> {
+    Sysctls::new().unprivileged_port_start()
+}
+
+/// Reads `net.ipv4.ip_local_port_range`, the ports the kernel hands out as
+/// source ports for outbound connections and implicit binds.
+///
+/// Returns [`DEFAULT_LOCAL_PORT_RANGE`] when the file is missing.
+pub fn local_port_range() -> io::Result<RangeInclusive<u16>> {
+    Sysctls::new().local_port_range()
+}
+
+/// Reads `net.ipv4.ip_local_reserved_ports`, the ports the kernel never
+/// hands out as ephemeral ports (e.g. `8000-8100,9090`).
+///
+/// Returns an empty set when the file is missing.
+pub fn reserved_ports() -> io::Result<PortRanges> {
+    Sysctls::new().reserved_ports()
+}
+
+/// `sys/net/ipv4/ip_local_port_range` as `net.ipv4.ip_local_port_range`.
+fn sysctl_name(path: &str) -> String {
+    path.trim_start_matches("sys/").replace('/', ".")
+}
+
+/// Ports below `start`, i.e. `1..=start - 1` (empty when `start <= 1`).
+pub(crate) fn privileged_range(start: u16) -> RangeInclusive<u16> {
+    1..=start.saturating_sub(1)
+}
+
+/// Ports from `start` upwards; port 0 is never included.
+pub(crate) fn unprivileged_range(start: u16) -> RangeInclusive<u16> {
+    start.max(1)..=65535
+}
+
+/// Parses a single port-valued sysctl such as `1024\n`.
+fn parse_port(contents: &str) -> io::Result<u16> {
+    contents.trim().parse().map_err(|_| invalid_value(contents))
+}
+
+/// Parses a `low<TAB>high` sysctl such as `32768\t60999\n`.
+fn parse_port_range(contents: &str) -> io::Result<RangeInclusive<u16>> {
+    let mut fields = contents.split_whitespace();
+    if let (Some(low), Some(high), None) = (fields.next(), fields.next(), fields.next())
+        && let (Ok(low), Ok(high)) = (parse_port(low), parse_port(high))
+        && low <= high
+    {
+        return Ok(low..=high);
+    }
+
+    Err(invalid_value(contents))
+}
+
+/// A [`WalledError::Parse`] for a single-line sysctl value.
+fn invalid_value(contents: &str) -> io::Error {
+    WalledError::Parse {
+        line_no: 1,
+        line: contents.trim().to_string(),
+    }
+    .into()
+}
+
+/// Reports a refused sysctl access as [`WalledError::PermissionDenied`].
+fn denied(e: io::Error, operation: impl FnOnce() -> String) -> io::Error {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        WalledError::PermissionDenied {
+            operation: operation(),
+        }
+        .into()
+    } else {
+        e
+    }
+}
+
+/// Removes the ports matching `drop`; `None` if nothing is left.
+fn without(ports: Option<Vec<u16>>, drop: impl Fn(u16) -> bool) -> Option<Vec<u16>> {
+    let ports: Vec<u16> = ports?.into_iter().filter(|port| !drop(*port)).collect();
+
+    if ports.is_empty() { None } else { Some(ports) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_port_values() {
+        assert_eq!(parse_port("1024\n").unwrap(), 1024);
+        assert_eq!(parse_port("0").unwrap(), 0);
+        assert_eq!(
+            parse_port("abc").unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn ranges_follow_the_boundary() {
+        assert_eq!(privileged_range(1024), 1..=1023);
+        assert_eq!(unprivileged_range(1024), 1024..=65535);
+
+        assert!(privileged_range(0).is_empty());
+        assert!(privileged_range(1).is_empty());
+        assert_eq!(unprivileged_range(0), 1..=65535);
+
+        assert_eq!(privileged_range(80), 1..=79);
+        assert_eq!(unprivileged_range(80), 80..=65535);
+    }
+
+    #[test]
+    fn parses_port_ranges() {
+        assert_eq!(parse_port_range("32768\t60999\n").unwrap(), 32768..=60999);
+        assert_eq!(parse_port_range("1024 1024").unwrap(), 1024..=1024);
+        for bad in ["", "32768", "60999\t32768", "1 2 3", "a\tb"] {
+            assert_eq!(
+                parse_port_range(bad).unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_ports() {
+        let layout = PortLayout::default();
+        assert_eq!(layout.classify(22), PortClass::Privileged);
+        assert_eq!(layout.classify(1024), PortClass::Registered);
+        assert_eq!(layout.classify(32767), PortClass::Registered);
+        assert_eq!(layout.classify(32768), PortClass::Ephemeral);
+        assert_eq!(layout.classify(60999), PortClass::Ephemeral);
+        assert_eq!(layout.classify(61000), PortClass::AboveRange);
+
+        let low = PortLayout::new(80, 1000..=2000);
+        assert_eq!(layout.classify(0), PortClass::Privileged);
+        assert_eq!(low.classify(79), PortClass::Privileged);
+        assert_eq!(low.classify(80), PortClass::Registered);
+        assert_eq!(low.classify(1500), PortClass::Ephemeral);
+        assert_eq!(
+            PortLayout::new(1024, 1..=5000).classify(22),
+            PortClass::Privileged
+        );
+    }
+
+    #[test]
+    fn reserved_ports_are_tagged_and_dropped() {
+        let reserved = "1000-1100,40000".parse().unwrap();
+        let layout = PortLayout::new(1024, 32768..=60999).with_reserved(reserved);
+        assert_eq!(layout.classify(1000), PortClass::Privileged);
+        assert_eq!(layout.classify(1100), PortClass::Reserved);
+        assert_eq!(layout.classify(40000), PortClass::Reserved);
+        assert_eq!(layout.classify(40001), PortClass::Ephemeral);
+        assert_eq!(
+            layout.without_reserved(Some(vec![1099, 1101, 40000])),
+            Some(vec![1101])
+        );
+    }
+
+    #[test]
+    fn drops_ephemeral_ports() {
+        let layout = PortLayout::new(1024, 5000..=6000);
+        assert_eq!(
+            layout.without_ephemeral(Some(vec![4999, 5000, 6000, 6001])),
+            Some(vec![4999, 6001])
+        );
+        assert_eq!(layout.without_ephemeral(Some(vec![5500])), None);
+        assert_eq!(layout.without_ephemeral(None), None);
+    }
+
+    /// A scratch procfs root under the system temp directory.
+    fn scratch_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("walled-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sys/net/ipv4")).unwrap();
+        root
+    }
+
+    #[test]
+    fn reads_sysctls_under_a_custom_root() {
+        let root = scratch_root("read");
+        let sysctls = Sysctls::with_root(&root);
+        assert_eq!(sysctls.layout().unwrap(), PortLayout::default());
+
+        fs::write(root.join(UNPRIVILEGED_PORT_START), "80\n").unwrap();
+        fs::write(root.join(LOCAL_PORT_RANGE), "40000\t50000\n").unwrap();
+        fs::write(root.join(LOCAL_RESERVED_PORTS), "8080\n").unwrap();
+        let layout = sysctls.layout().unwrap();
+        assert_eq!(layout.unprivileged_port_start(), 80);
+        assert_eq!(layout.ephemeral(), 40000..=50000);
+        assert_eq!(layout.classify(8080), PortClass::Reserved);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn reserve_and_unreserve_keep_other_entries() {
+        let root = scratch_root("reserve");
+        let sysctls = Sysctls::with_root(&root);
+        let path = root.join(LOCAL_RESERVED_PORTS);
+        fs::write(&path, "8000-8100,9090\n").unwrap();
+
+        let ports = sysctls.reserve(8101..=8200).unwrap();
+        assert_eq!(ports.ranges(), &[8000..=8200, 9090..=9090]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "8000-8200,9090\n");
+
+        sysctls.reserve(50000..=50000).unwrap();
+        let ports = sysctls.unreserve(8050..=8060).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "8000-8049,8061-8200,9090,50000\n"
+        );
+        assert_eq!(sysctls.reserved_ports().unwrap(), ports);
+
+        sysctls.set_reserved_ports(&PortRanges::new()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\n");
+        assert!(sysctls.reserved_ports().unwrap().is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn layout_fails_on_a_malformed_sysctl() {
+        let root = scratch_root("layout");
+        let sysctls = Sysctls::with_root(&root);
+        fs::write(root.join(UNPRIVILEGED_PORT_START), "512\n").unwrap();
+        fs::write(root.join(LOCAL_PORT_RANGE), "60999\t32768\n").unwrap();
+
+        let err = sysctls.layout().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        fs::write(root.join(LOCAL_PORT_RANGE), "32768\t60999\n").unwrap();
+        let layout = sysctls.layout().unwrap();
+        assert_eq!(layout, PortLayout::new(512, 32768..=60999));
+        assert_eq!(layout.classify(600), PortClass::Registered);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn reads_the_unprivileged_port_start() {
+        let root = scratch_root("start");
+        let sysctls = Sysctls::with_root(&root);
+        assert_eq!(sysctls.unprivileged_port_start().unwrap(), 1024);
+
+        fs::write(root.join(UNPRIVILEGED_PORT_START), "0\n").unwrap();
+        assert_eq!(sysctls.unprivileged_port_start().unwrap(), 0);
+
+        fs::write(root.join(UNPRIVILEGED_PORT_START), "1024x\n").unwrap();
+        let err = sysctls.unprivileged_port_start().unwrap_err();
+        assert!(matches!(
+            WalledError::of(&err),
+            Some(WalledError::Parse { line, .. }) if line == "1024x"
+        ));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,307 +1,284 @@
-use std::collections::HashSet;
 use std::io;
-use std::process::{Command, Stdio};
+use std::ops::RangeInclusive;
 
-/// Returns the list of *privileged* (1‑1023) TCP ports that are currently
+use crate::portset::PortSet;
+use crate::query::PortQuery;
+use crate::socket::{Protocol, Socket};
+use crate::source::SourceChain;
+use crate::sysctl::{IANA_UNPRIVILEGED_PORT_START, privileged_range, unprivileged_range};
+
+/// Returns the list of *privileged* TCP ports that are currently
 /// **listening** on the host.
 ///
+/// Privileged ports are those below `net.ipv4.ip_unprivileged_port_start`
+/// (1‑1023 with the kernel default); see [`privileged_tcp_used_iana`] for
+/// the fixed IANA split.
+///
+/// Like every function of this module, it scans with
+/// [`SourceChain::default`], which lists the backends it tries.
+///
 /// Success variants:
 ///   * `Ok(Some(vec))` – at least one port was found.
-///   * `Ok(None)`      – the command ran fine but no privileged TCP ports are listening (empty set).
+///   * `Ok(None)`      – the scan ran fine but no privileged TCP ports are listening (empty set).
 ///
 /// Failure variant:
-///   * `Err(e)` – the `ss` command could not be started, exited with a non‑zero status,
-///                or its output could not be parsed.
-///
-/// The implementation avoids any shell pipelines; it invokes `ss` directly and
-/// performs all filtering in Rust.
+///   * `Err(e)` – none of the backends of the default [`SourceChain`] could be
+///     queried, the sysctl could not be read, or their output could not be parsed.
 pub fn privileged_tcp_used() -> io::Result<Option<Vec<u16>>> {
-    let output = Command::new("ss")
-        .args(&["-tlnH"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()?;
-
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("`ss` exited with status {}", output.status),
-        ));
-    }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut used_ports = Vec::new();
-
-    for line in stdout.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 4 {
-            continue;
-        }
-        let local = parts[3];
-        if let Some(port_str) = local.rsplit(':').next() {
-            if let Ok(port) = port_str.parse::<u16>() {
-                if (1..=1023).contains(&port) {
-                    used_ports.push(port);
-                }
-            }
-        }
-    }
-
-    if used_ports.is_empty() {
-        Ok(None)
-    } else {
-        used_ports.sort_unstable();
-        used_ports.dedup();
-        Ok(Some(used_ports))
-    }
+    privileged_tcp_used_from(&SourceChain::default())
 }
 
-/// Returns the list of *privileged* (1‑1023) TCP ports that are **not**
+/// Returns the list of *privileged* TCP ports that are **not**
 /// currently listening on the host.
 ///
+/// Privileged ports are those below `net.ipv4.ip_unprivileged_port_start`
+/// (1‑1023 with the kernel default); see [`privileged_tcp_free_iana`] for
+/// the fixed IANA split.
+///
 /// Success variants:
 ///   * `Ok(Some(vec))` – at least one free privileged TCP port was found.
-///   * `Ok(None)`      – every privileged TCP port (1‑1023) is in use (empty free set).
+///   * `Ok(None)`      – every privileged TCP port is in use (empty free set).
 ///
 /// Failure variant:
-///   * `Err(e)` – the `ss` command could not be started, exited with a non‑zero status,
-///                or its output could not be parsed.
-///
-/// The implementation avoids any shell pipelines; it invokes `ss` directly and
-/// performs all set arithmetic in pure Rust.
+///   * `Err(e)` – none of the backends of the default [`SourceChain`] could be
+///     queried, the sysctl could not be read, or their output could not be parsed.
 pub fn privileged_tcp_free() -> io::Result<Option<Vec<u16>>> {
-    let output = Command::new("ss")
-        .args(&["-tlnH"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()?;
-
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("`ss` exited with status {}", output.status),
-        ));
-    }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut used = HashSet::new();
-
-    for line in stdout.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 4 {
-            continue;
-        }
-        let local = parts[3];
-        if let Some(port_str) = local.rsplit(':').next() {
-            if let Ok(port) = port_str.parse::<u16>() {
-                if (1..=1023).contains(&port) {
-                    used.insert(port);
-                }
-            }
-        }
-    }
-
-    let mut free_ports = Vec::new();
-    for port in 1u16..=1023 {
-        if !used.contains(&port) {
-            free_ports.push(port);
-        }
-    }
-
-    if free_ports.is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(free_ports))
-    }
+    privileged_tcp_free_from(&SourceChain::default())
 }
 
-/// Returns the list of *unprivileged* (1024‑65535) TCP ports that are
-/// currently **listening** on the host.
+/// Returns the list of *unprivileged* TCP ports that are currently
+/// **listening** on the host.
+///
+/// Unprivileged ports start at `net.ipv4.ip_unprivileged_port_start`
+/// (1024‑65535 with the kernel default); see [`unprivileged_tcp_used_iana`] for
+/// the fixed IANA split.
 ///
 /// Success variants:
 ///   * `Ok(Some(vec))` – at least one unprivileged TCP port is in use.
 ///   * `Ok(None)`      – no unprivileged TCP ports are listening (empty set).
 ///
 /// Failure variant:
-///   * `Err(e)` – the `ss` command could not be started, exited with a non‑zero
-///                status, or its output could not be parsed.
-///
-/// The implementation avoids any shell pipelines; it invokes `ss` directly and
-/// performs all filtering in pure Rust.
+///   * `Err(e)` – none of the backends of the default [`SourceChain`] could be
+///     queried, the sysctl could not be read, or their output could not be parsed.
 pub fn unprivileged_tcp_used() -> io::Result<Option<Vec<u16>>> {
-    let output = Command::new("ss")
-        .args(&["-tlnH"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()?;
+    unprivileged_tcp_used_from(&SourceChain::default())
+}
+
+/// Returns the list of *unprivileged* TCP ports that are **not**
+/// currently listening on the host.
+///
+/// Unprivileged ports start at `net.ipv4.ip_unprivileged_port_start`
+/// (1024‑65535 with the kernel default); see [`unprivileged_tcp_free_iana`] for
+/// the fixed IANA split.
+///
+/// Success variants:
+///   * `Ok(Some(vec))` – at least one free unprivileged TCP port was found.
+///   * `Ok(None)`      – every unprivileged TCP port is in use (empty free set).
+///
+/// Failure variant:
+///   * `Err(e)` – none of the backends of the default [`SourceChain`] could be
+///     queried, the sysctl could not be read, or their output could not be parsed.
+pub fn unprivileged_tcp_free() -> io::Result<Option<Vec<u16>>> {
+    unprivileged_tcp_free_from(&SourceChain::default())
+}
+
+/// Returns the *unprivileged* TCP ports that are **not** currently listening
+/// and lie outside the kernel's ephemeral range
+/// (`net.ipv4.ip_local_port_range`, 32768‑60999 by default).
+///
+/// These are the ports it is safe to pick for a new service: a port in the
+/// ephemeral range may be taken as the source port of an outbound
+/// connection before the service gets to bind it.
+///
+/// Success variants:
+///   * `Ok(Some(vec))` – at least one such port was found.
+///   * `Ok(None)`      – every unprivileged, non-ephemeral TCP port is in use.
+///
+/// Failure variant:
+///   * `Err(e)` – none of the backends of the default [`SourceChain`] could be
+///     queried, the sysctls could not be read, or their output could not be parsed.
+pub fn unprivileged_tcp_free_non_ephemeral() -> io::Result<Option<Vec<u16>>> {
+    unprivileged_tcp_free_non_ephemeral_from(&SourceChain::default())
+}
 
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("`ss` exited with status {}", output.status),
-        ));
-    }
+/// Returns the *unprivileged* TCP ports that are **not** currently listening
+/// and are not listed in `net.ipv4.ip_local_reserved_ports`.
+///
+/// Reserved ports are set aside by the administrator for specific services,
+/// so they should not be picked for something else even when nothing
+/// listens on them yet. Use [`PortLayout::is_reserved`](crate::PortLayout::is_reserved) to tag them instead.
+///
+/// Success variants:
+///   * `Ok(Some(vec))` – at least one such port was found.
+///   * `Ok(None)`      – every unprivileged, unreserved TCP port is in use.
+///
+/// Failure variant:
+///   * `Err(e)` – none of the backends of the default [`SourceChain`] could be
+///     queried, the sysctls could not be read, or their output could not be parsed.
+pub fn unprivileged_tcp_free_unreserved() -> io::Result<Option<Vec<u16>>> {
+    unprivileged_tcp_free_unreserved_from(&SourceChain::default())
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut used = HashSet::new();
+/// Like [`privileged_tcp_used`], but always uses the classic IANA split
+/// (1‑1023), whatever `ip_unprivileged_port_start` is set to.
+pub fn privileged_tcp_used_iana() -> io::Result<Option<Vec<u16>>> {
+    privileged_tcp_used_iana_from(&SourceChain::default())
+}
 
-    for line in stdout.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 4 {
-            continue;
-        }
-        let local = parts[3];
-        if let Some(port_str) = local.rsplit(':').next() {
-            if let Ok(port) = port_str.parse::<u16>() {
-                if (1024..=65535).contains(&port) {
-                    used.insert(port);
-                }
-            }
-        }
-    }
+/// Like [`privileged_tcp_free`], but always uses the classic IANA split
+/// (1‑1023), whatever `ip_unprivileged_port_start` is set to.
+pub fn privileged_tcp_free_iana() -> io::Result<Option<Vec<u16>>> {
+    privileged_tcp_free_iana_from(&SourceChain::default())
+}
 
-    if used.is_empty() {
-        return Ok(None);
-    }
+/// Like [`unprivileged_tcp_used`], but always uses the classic IANA split
+/// (1024‑65535), whatever `ip_unprivileged_port_start` is set to.
+pub fn unprivileged_tcp_used_iana() -> io::Result<Option<Vec<u16>>> {
+    unprivileged_tcp_used_iana_from(&SourceChain::default())
+}
 
-    let mut ports: Vec<u16> = used.into_iter().collect();
-    ports.sort_unstable();
-    Ok(Some(ports))
+/// Like [`unprivileged_tcp_free`], but always uses the classic IANA split
+/// (1024‑65535), whatever `ip_unprivileged_port_start` is set to.
+pub fn unprivileged_tcp_free_iana() -> io::Result<Option<Vec<u16>>> {
+    unprivileged_tcp_free_iana_from(&SourceChain::default())
 }
 
-/// Returns the list of *unprivileged* (1024‑65535) TCP ports that are **not**
-/// currently listening on the host.
+/// Returns the TCP ports within `range` that are currently **listening**,
+/// as a [`PortSet`].
 ///
-/// Success variants:
-///   * `Ok(Some(vec))` – at least one free unprivileged TCP port was found.
-///   * `Ok(None)`      – every unprivileged TCP port (1024‑65535) is in use
-///                        (empty free set).
+/// Failure variant:
+///   * `Err(e)` – none of the backends of the default [`SourceChain`] could be
+///     queried, or their output could not be parsed.
+pub fn tcp_used_set(range: RangeInclusive<u16>) -> io::Result<PortSet> {
+    tcp_used_set_from(&SourceChain::default(), range)
+}
+
+/// Returns the TCP ports within `range` that are **not** currently
+/// listening, as a [`PortSet`].
 ///
 /// Failure variant:
-///   * `Err(e)` – the `ss` command could not be started, exited with a non‑zero
-///                status, or its output could not be parsed.
+///   * `Err(e)` – none of the backends of the default [`SourceChain`] could be
+///     queried, or their output could not be parsed.
+pub fn tcp_free_set(range: RangeInclusive<u16>) -> io::Result<PortSet> {
+    tcp_free_set_from(&SourceChain::default(), range)
+}
+
+/// Returns every TCP socket that is currently **listening** on the host,
+/// IPv4 and IPv6 combined.
 ///
-/// The implementation avoids any shell pipelines; it invokes `ss` directly and
-/// performs all set arithmetic in pure Rust.
-pub fn unprivileged_tcp_free() -> io::Result<Option<Vec<u16>>> {
-    let output = Command::new("ss")
-        .args(&["-tlnH"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()?;
+/// Unlike the port-only functions, each [`Socket`] keeps the bound address,
+/// state and queue sizes, plus the inode and owner UID when the backend
+/// reports them.
+///
+/// Failure variant:
+///   * `Err(e)` – none of the backends of the default [`SourceChain`] could be
+///     queried, or their output could not be parsed.
+pub fn tcp_sockets() -> io::Result<Vec<Socket>> {
+    tcp_sockets_from(&SourceChain::default())
+}
+
+// The bodies of the functions above, run against any chain.
+
+pub(crate) fn privileged_tcp_used_from(chain: &SourceChain) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .privileged()
+        .used()
+        .run_from(chain)?
+        .to_vec())
+}
+
+pub(crate) fn privileged_tcp_free_from(chain: &SourceChain) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .privileged()
+        .free()
+        .run_from(chain)?
+        .to_vec())
+}
+
+pub(crate) fn unprivileged_tcp_used_from(chain: &SourceChain) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .unprivileged()
+        .used()
+        .run_from(chain)?
+        .to_vec())
+}
 
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("`ss` exited with status {}", output.status),
-        ));
-    }
+pub(crate) fn unprivileged_tcp_free_from(chain: &SourceChain) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .unprivileged()
+        .free()
+        .run_from(chain)?
+        .to_vec())
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut used = HashSet::new();
+pub(crate) fn unprivileged_tcp_free_non_ephemeral_from(
+    chain: &SourceChain,
+) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .unprivileged()
+        .free()
+        .without_ephemeral()
+        .run_from(chain)?
+        .to_vec())
+}
 
-    for line in stdout.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() < 4 {
-            continue;
-        }
-        let local = parts[3];
-        if let Some(port_str) = local.rsplit(':').next() {
-            if let Ok(port) = port_str.parse::<u16>() {
-                if (1024..=65535).contains(&port) {
-                    used.insert(port);
-                }
-            }
-        }
-    }
+pub(crate) fn unprivileged_tcp_free_unreserved_from(
+    chain: &SourceChain,
+) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .unprivileged()
+        .free()
+        .without_reserved()
+        .run_from(chain)?
+        .to_vec())
+}
 
-    let mut free_ports = Vec::new();
-    for port in 1024u16..=65535 {
-        if !used.contains(&port) {
-            free_ports.push(port);
-        }
-    }
+pub(crate) fn privileged_tcp_used_iana_from(chain: &SourceChain) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .range(privileged_range(IANA_UNPRIVILEGED_PORT_START))
+        .used()
+        .run_from(chain)?
+        .to_vec())
+}
 
-    if free_ports.is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(free_ports))
-    }
+pub(crate) fn privileged_tcp_free_iana_from(chain: &SourceChain) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .range(privileged_range(IANA_UNPRIVILEGED_PORT_START))
+        .free()
+        .run_from(chain)?
+        .to_vec())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+pub(crate) fn unprivileged_tcp_used_iana_from(chain: &SourceChain) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .range(unprivileged_range(IANA_UNPRIVILEGED_PORT_START))
+        .used()
+        .run_from(chain)?
+        .to_vec())
+}
 
-    #[test]
-    fn unprivileged_tcp_free_test() {
-        match unprivileged_tcp_free() {
-            Ok(Some(ports)) => {
-                println!(
-                    "Unprivileged TCP ports FREE ({} total): {:?}",
-                    ports.len(),
-                    ports
-                );
-            }
-            Ok(None) => {
-                println!("All unprivileged TCP ports (1024‑65535) are currently listening.");
-            }
-            Err(e) => {
-                eprintln!("Failed to run `ss`: {}", e);
-            }
-        }
-    }
+pub(crate) fn unprivileged_tcp_free_iana_from(chain: &SourceChain) -> io::Result<Option<Vec<u16>>> {
+    Ok(PortQuery::tcp()
+        .range(unprivileged_range(IANA_UNPRIVILEGED_PORT_START))
+        .free()
+        .run_from(chain)?
+        .to_vec())
+}
 
-    #[test]
-    fn unprivileged_tcp_used_test() {
-        match unprivileged_tcp_used() {
-            Ok(Some(ports)) => {
-                println!(
-                    "Unprivileged TCP ports IN USE ({} total): {:?}",
-                    ports.len(),
-                    ports
-                );
-            }
-            Ok(None) => {
-                println!("No unprivileged TCP ports (1024‑65535) are currently listening.");
-            }
-            Err(e) => {
-                eprintln!("Failed to run `ss`: {}", e);
-            }
-        }
-    }
+pub(crate) fn tcp_used_set_from(
+    chain: &SourceChain,
+    range: RangeInclusive<u16>,
+) -> io::Result<PortSet> {
+    PortQuery::tcp().range(range).used().run_from(chain)
+}
 
-    #[test]
-    fn privileged_tcp_free_test() {
-        match privileged_tcp_free() {
-            Ok(Some(ports)) => {
-                println!(
-                    "Privileged TCP ports FREE ({} total): {:?}",
-                    ports.len(),
-                    ports
-                );
-            }
-            Ok(None) => {
-                println!("All privileged TCP ports (1‑1023) are currently listening.");
-            }
-            Err(e) => {
-                eprintln!("Failed to run `ss`: {}", e);
-            }
-        }
-    }
+pub(crate) fn tcp_free_set_from(
+    chain: &SourceChain,
+    range: RangeInclusive<u16>,
+) -> io::Result<PortSet> {
+    PortQuery::tcp().range(range).free().run_from(chain)
+}
 
-    #[test]
-    fn privileged_tcp_used_test() {
-        match privileged_tcp_used() {
-            Ok(Some(ports)) => {
-                println!("Privileged TCP ports in use ({} total): {:?}", ports.len(), ports);
-            }
-            Ok(None) => {
-                println!("No privileged TCP ports are currently listening.");
-            }
-            Err(e) => {
-                eprintln!("Failed to run `ss`: {}", e);
-            }
-        }
-    }
+pub(crate) fn tcp_sockets_from(chain: &SourceChain) -> io::Result<Vec<Socket>> {
+    Ok(chain.listening_sockets(Protocol::Tcp)?.sockets)
 }
@@ -0,0 +1,224 @@
+//! Stub sockets and sources shared by the unit tests.
+
+use std::io;
+
+use crate::socket::{Family, Protocol, Socket, SocketState};
+use crate::source::{SocketSource, SourceChain};
+
+/// A listening IPv4 wildcard socket on `port`, as `ss` would report it.
+pub(crate) fn listener(protocol: Protocol, port: u16) -> Socket {
+    Socket {
+        protocol,
+        family: Family::V4,
+        local_addr: [0, 0, 0, 0].into(),
+        port,
+        remote: None,
+        state: match protocol {
+            Protocol::Tcp => SocketState::Listen,
+            Protocol::Udp => SocketState::Unconnected,
+        },
+        recv_q: 0,
+        send_q: 0,
+        inode: None,
+        uid: None,
+        v6only: None,
+        interface: None,
+    }
+}
+
+/// A source that reports a [`listener`] on each of its TCP and UDP ports.
+pub(crate) struct Listening {
+    pub tcp: &'static [u16],
+    pub udp: &'static [u16],
+}
+
+impl Listening {
+    /// A chain holding only this source.
+    pub(crate) fn chain(self) -> SourceChain {
+        SourceChain::new().with(self)
+    }
+}
+
+impl SocketSource for Listening {
+    fn name(&self) -> &'static str {
+        "stub"
+    }
+
+    fn listening_sockets(&self, protocol: Protocol) -> io::Result<Vec<Socket>> {
+        let ports = match protocol {
+            Protocol::Tcp => self.tcp,
+            Protocol::Udp => self.udp,
+        };
+        Ok(ports.iter().map(|&port| listener(protocol, port)).collect())
+    }
+}
+
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use super::*;
+    use crate::sysctl::{
+        IANA_UNPRIVILEGED_PORT_START, PortLayout, privileged_range, unprivileged_range,
+    };
+    use crate::{tcp, udp};
+
+    const TCP: &[u16] = &[22, 443, 8080, 40000];
+    const UDP: &[u16] = &[53, 5353];
+
+    type Ports = fn(&SourceChain) -> io::Result<Option<Vec<u16>>>;
+    type Skipped = fn(&PortLayout, u16) -> bool;
+
+    fn stub() -> SourceChain {
+        Listening { tcp: TCP, udp: UDP }.chain()
+    }
+
+    /// Checks a pair of used/free functions over `range` against the stub.
+    fn check(used: Ports, free: Ports, range: RangeInclusive<u16>, listening: &[u16]) {
+        let chain = stub();
+        let expected: Vec<u16> = listening
+            .iter()
+            .copied()
+            .filter(|port| range.contains(port))
+            .collect();
+        let used = used(&chain).unwrap().unwrap_or_default();
+        assert_eq!(used, expected, "{:?}", range);
+
+        let free = free(&chain).unwrap().unwrap_or_default();
+        assert_eq!(
+            free.len() + used.len(),
+            range.clone().count(),
+            "{:?}",
+            range
+        );
+        assert!(
+            free.iter()
+                .all(|port| range.contains(port) && !used.contains(port))
+        );
+    }
+
+    #[test]
+    fn wrappers_split_ports_at_the_sysctl() {
+        let layout = PortLayout::current().unwrap();
+        let (low, high) = (layout.privileged_range(), layout.unprivileged_range());
+        check(
+            tcp::privileged_tcp_used_from,
+            tcp::privileged_tcp_free_from,
+            low.clone(),
+            TCP,
+        );
+        check(
+            tcp::unprivileged_tcp_used_from,
+            tcp::unprivileged_tcp_free_from,
+            high.clone(),
+            TCP,
+        );
+        check(
+            udp::privileged_udp_used_from,
+            udp::privileged_udp_free_from,
+            low,
+            UDP,
+        );
+        check(
+            udp::unprivileged_udp_used_from,
+            udp::unprivileged_udp_free_from,
+            high,
+            UDP,
+        );
+    }
+
+    #[test]
+    fn iana_wrappers_split_ports_at_1024() {
+        let low = privileged_range(IANA_UNPRIVILEGED_PORT_START);
+        let high = unprivileged_range(IANA_UNPRIVILEGED_PORT_START);
+        check(
+            tcp::privileged_tcp_used_iana_from,
+            tcp::privileged_tcp_free_iana_from,
+            low.clone(),
+            TCP,
+        );
+        check(
+            tcp::unprivileged_tcp_used_iana_from,
+            tcp::unprivileged_tcp_free_iana_from,
+            high.clone(),
+            TCP,
+        );
+        check(
+            udp::privileged_udp_used_iana_from,
+            udp::privileged_udp_free_iana_from,
+            low,
+            UDP,
+        );
+        check(
+            udp::unprivileged_udp_used_iana_from,
+            udp::unprivileged_udp_free_iana_from,
+            high,
+            UDP,
+        );
+    }
+
+    #[test]
+    fn free_wrappers_can_skip_ephemeral_and_reserved_ports() {
+        let layout = PortLayout::current().unwrap();
+        let chain = stub();
+        let cases: [(Ports, Skipped, &[u16]); 4] = [
+            (
+                tcp::unprivileged_tcp_free_non_ephemeral_from,
+                PortLayout::is_ephemeral,
+                TCP,
+            ),
+            (
+                udp::unprivileged_udp_free_non_ephemeral_from,
+                PortLayout::is_ephemeral,
+                UDP,
+            ),
+            (
+                tcp::unprivileged_tcp_free_unreserved_from,
+                PortLayout::is_reserved,
+                TCP,
+            ),
+            (
+                udp::unprivileged_udp_free_unreserved_from,
+                PortLayout::is_reserved,
+                UDP,
+            ),
+        ];
+        for (free, skipped, listening) in cases {
+            let expected: Vec<u16> = layout
+                .unprivileged_range()
+                .filter(|&port| !listening.contains(&port) && !skipped(&layout, port))
+                .collect();
+            assert_eq!(free(&chain).unwrap().unwrap_or_default(), expected);
+        }
+    }
+
+    #[test]
+    fn set_and_socket_wrappers() {
+        let chain = stub();
+        let used = tcp::tcp_used_set_from(&chain, 1..=1023).unwrap();
+        assert_eq!(used.iter().collect::<Vec<_>>(), vec![22, 443]);
+        let free = udp::udp_free_set_from(&chain, 50..=60).unwrap();
+        assert_eq!(free.len(), 10);
+        assert!(!free.contains(53));
+        assert!(
+            udp::udp_used_set_from(&chain, 5000..=6000)
+                .unwrap()
+                .contains(5353)
+        );
+        assert_eq!(
+            tcp::tcp_free_set_from(&chain, 8080..=8080).unwrap().len(),
+            0
+        );
+
+        let sockets = tcp::tcp_sockets_from(&chain).unwrap();
+        assert_eq!(sockets.len(), TCP.len());
+        assert!(
+            sockets
+                .iter()
+                .all(|socket| socket.protocol == Protocol::Tcp)
+        );
+        assert_eq!(
+            udp::udp_sockets_from(&chain).unwrap()[1],
+            listener(Protocol::Udp, 5353)
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cancel::{CancelHandle, POLL_INTERVAL};
+use crate::socket::Protocol;
+use crate::source::SourceChain;
+
+/// A listening socket that appeared or went away between two scans of a
+/// [`Watcher`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PortEvent {
+    /// Something started listening on `addr:port`.
+    Opened {
+        proto: Protocol,
+        addr: IpAddr,
+        port: u16,
+    },
+    /// Nothing listens on `addr:port` any more.
+    Closed {
+        proto: Protocol,
+        addr: IpAddr,
+        port: u16,
+    },
+}
+
+impl PortEvent {
+    /// Protocol of the socket.
+    pub fn proto(&self) -> Protocol {
+        match self {
+            PortEvent::Opened { proto, .. } | PortEvent::Closed { proto, .. } => *proto,
+        }
+    }
+
+    /// Local address of the socket.
+    pub fn addr(&self) -> IpAddr {
+        match self {
+            PortEvent::Opened { addr, .. } | PortEvent::Closed { addr, .. } => *addr,
+        }
+    }
+
+    /// Local port of the socket.
+    pub fn port(&self) -> u16 {
+        match self {
+            PortEvent::Opened { port, .. } | PortEvent::Closed { port, .. } => *port,
+        }
+    }
+}
+
+/// Prints `opened tcp 0.0.0.0:22`, with IPv6 addresses in brackets.
+impl fmt::Display for PortEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = match self {
+            PortEvent::Opened { .. } => "opened",
+            PortEvent::Closed { .. } => "closed",
+        };
+        match self.addr() {
+            IpAddr::V4(addr) => write!(f, "{} {} {}:{}", verb, self.proto(), addr, self.port()),
+            IpAddr::V6(addr) => write!(f, "{} {} [{}]:{}", verb, self.proto(), addr, self.port()),
+        }
+    }
+}
+
+/// A listening socket as the watcher tracks it.
+type Key = (Protocol, IpAddr, u16);
+
+/// Rescans the host at a fixed interval and reports the listening sockets
+/// that were opened or closed in between.
+///
+/// The first scan is the baseline and reports nothing, unless
+/// [`Watcher::report_existing`] is set. Use it as an iterator, which sleeps
+/// between scans and ends once its [`CancelHandle`] is cancelled:
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use walled::Watcher;
+///
+/// for event in Watcher::new(Duration::from_secs(2)).debounce(Duration::from_secs(5)) {
+///     println!("{}", event?);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// or call [`Watcher::poll`] from your own loop. A failed scan is yielded as
+/// an error and the watcher tries again after the next interval.
+pub struct Watcher {
+    chain: SourceChain,
+    interval: Duration,
+    debounce: Duration,
+    protocols: Vec<Protocol>,
+    cancel: Option<CancelHandle>,
+    report_existing: bool,
+    /// Sockets last reported as open; `None` before the baseline scan.
+    reported: Option<BTreeSet<Key>>,
+    /// Sockets whose state differs from `reported`, since when.
+    pending: HashMap<Key, Instant>,
+    events: VecDeque<PortEvent>,
+    last_scan: Option<Instant>,
+}
+
+impl Watcher {
+    /// Watches TCP and UDP with the default [`SourceChain`], scanning every
+    /// `interval`.
+    pub fn new(interval: Duration) -> Watcher {
+        Watcher::with_chain(SourceChain::default(), interval)
+    }
+
+    /// Watches TCP and UDP with `chain`, scanning every `interval`.
+    pub fn with_chain(chain: SourceChain, interval: Duration) -> Watcher {
+        Watcher {
+            chain,
+            interval,
+            debounce: Duration::ZERO,
+            protocols: vec![Protocol::Tcp, Protocol::Udp],
+            cancel: None,
+            report_existing: false,
+            reported: None,
+            pending: HashMap::new(),
+            events: VecDeque::new(),
+            last_scan: None,
+        }
+    }
+
+    /// Only reports a change once it has lasted `debounce`, so a socket
+    /// that flaps closed and open again in between is not reported at all.
+    pub fn debounce(mut self, debounce: Duration) -> Watcher {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Only watches sockets of the protocols in `protocols`.
+    pub fn protocols(mut self, protocols: impl IntoIterator<Item = Protocol>) -> Watcher {
+        self.protocols = protocols.into_iter().collect();
+        self.protocols.sort_unstable();
+        self.protocols.dedup();
+        self
+    }
+
+    /// Ends the iterator, and interrupts its sleep, once `handle` is
+    /// cancelled.
+    pub fn with_cancel(mut self, handle: CancelHandle) -> Watcher {
+        self.cancel = Some(handle);
+        self
+    }
+
+    /// Reports every socket found by the first scan as [`PortEvent::Opened`].
+    pub fn report_existing(mut self) -> Watcher {
+        self.report_existing = true;
+        self
+    }
+
+    /// Scans now and returns the changes since the previous scan, without
+    /// waiting for the interval.
+    pub fn poll(&mut self) -> io::Result<Vec<PortEvent>> {
+        let now = Instant::now();
+        self.last_scan = Some(now);
+
+        let mut current = BTreeSet::new();
+        for protocol in &self.protocols {
+            let answer = self.chain.listening_sockets(*protocol)?;
+            current.extend(
+                answer
+                    .sockets
+                    .iter()
+                    .map(|socket| (socket.protocol, socket.local_addr, socket.port)),
+            );
+        }
+
+        if self.reported.is_none() && !self.report_existing {
+            self.reported = Some(current);
+            return Ok(Vec::new());
+        }
+        Ok(self.settle(current, now))
+    }
+
+    /// Diffs `current` against the reported sockets and reports the changes
+    /// that have lasted for the debounce time.
+    fn settle(&mut self, current: BTreeSet<Key>, now: Instant) -> Vec<PortEvent> {
+        let reported = self.reported.get_or_insert_with(BTreeSet::new);
+        let changed: BTreeSet<Key> = reported.symmetric_difference(&current).copied().collect();
+
+        // A change that reverted before it settled is forgotten.
+        self.pending.retain(|key, _| changed.contains(key));
+
+        let mut events = Vec::new();
+        for key in changed {
+            let since = *self.pending.entry(key).or_insert(now);
+            if now.duration_since(since) < self.debounce {
+                continue;
+            }
+            self.pending.remove(&key);
+
+            let (proto, addr, port) = key;
+            if current.contains(&key) {
+                reported.insert(key);
+                events.push(PortEvent::Opened { proto, addr, port });
+            } else {
+                reported.remove(&key);
+                events.push(PortEvent::Closed { proto, addr, port });
+            }
+        }
+
+        events
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelHandle::is_cancelled)
+    }
+
+    /// Sleeps until the next scan is due; `false` if cancelled meanwhile.
+    fn sleep_until_due(&self) -> bool {
+        let Some(last_scan) = self.last_scan else {
+            return !self.is_cancelled();
+        };
+        let due = last_scan + self.interval;
+        loop {
+            if self.is_cancelled() {
+                return false;
+            }
+            let left = due.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return true;
+            }
+            thread::sleep(left.min(POLL_INTERVAL));
+        }
+    }
+}
+
+/// Yields each event as it is found, sleeping between scans. Never ends
+/// unless the watcher was given a [`CancelHandle`].
+impl Iterator for Watcher {
+    type Item = io::Result<PortEvent>;
+
+    fn next(&mut self) -> Option<io::Result<PortEvent>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+            if !self.sleep_until_due() {
+                return None;
+            }
+            match self.poll() {
+                Ok(events) => self.events.extend(events),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::Socket;
+    use crate::source::SocketSource;
+    use crate::testing::listener;
+    use std::sync::{Arc, Mutex};
+
+    /// A source whose listening ports the test changes between scans.
+    #[derive(Clone, Default)]
+    struct Scripted(Arc<Mutex<Vec<u16>>>);
+
+    impl Scripted {
+        fn set(&self, ports: &[u16]) {
+            *self.0.lock().unwrap() = ports.to_vec();
+        }
+    }
+
+    impl SocketSource for Scripted {
+        fn name(&self) -> &'static str {
+            "scripted"
+        }
+
+        fn listening_sockets(&self, protocol: Protocol) -> io::Result<Vec<Socket>> {
+            if protocol == Protocol::Udp {
+                return Ok(Vec::new());
+            }
+            let ports = self.0.lock().unwrap().clone();
+            Ok(ports
+                .into_iter()
+                .map(|port| listener(protocol, port))
+                .collect())
+        }
+    }
+
+    fn opened(port: u16) -> PortEvent {
+        PortEvent::Opened {
+            proto: Protocol::Tcp,
+            addr: [0, 0, 0, 0].into(),
+            port,
+        }
+    }
+
+    fn closed(port: u16) -> PortEvent {
+        PortEvent::Closed {
+            proto: Protocol::Tcp,
+            addr: [0, 0, 0, 0].into(),
+            port,
+        }
+    }
+
+    fn watcher(source: &Scripted) -> Watcher {
+        let chain = SourceChain::new().with(source.clone());
+        Watcher::with_chain(chain, Duration::from_millis(1))
+    }
+
+    #[test]
+    fn reports_changes_after_the_baseline() {
+        let source = Scripted::default();
+        source.set(&[22, 80]);
+        let mut watcher = watcher(&source);
+
+        assert_eq!(watcher.poll().unwrap(), vec![]);
+        source.set(&[22, 443]);
+        assert_eq!(watcher.poll().unwrap(), vec![closed(80), opened(443)]);
+        assert_eq!(watcher.poll().unwrap(), vec![]);
+        assert_eq!(opened(443).to_string(), "opened tcp 0.0.0.0:443");
+    }
+
+    #[test]
+    fn can_report_the_baseline() {
+        let source = Scripted::default();
+        source.set(&[22]);
+        let mut watcher = watcher(&source).report_existing();
+        assert_eq!(watcher.poll().unwrap(), vec![opened(22)]);
+    }
+
+    #[test]
+    fn debounce_hides_flapping_sockets() {
+        let source = Scripted::default();
+        source.set(&[22]);
+        let mut watcher = watcher(&source).debounce(Duration::from_millis(50));
+        watcher.poll().unwrap();
+
+        // 22 flaps closed and back within the debounce window.
+        source.set(&[]);
+        assert_eq!(watcher.poll().unwrap(), vec![]);
+        source.set(&[22]);
+        assert_eq!(watcher.poll().unwrap(), vec![]);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(watcher.poll().unwrap(), vec![]);
+
+        // 8080 stays open long enough to be reported.
+        source.set(&[22, 8080]);
+        assert_eq!(watcher.poll().unwrap(), vec![]);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(watcher.poll().unwrap(), vec![opened(8080)]);
+    }
+
+    #[test]
+    fn iterator_yields_events_until_cancelled() {
+        let source = Scripted::default();
+        let cancel = CancelHandle::new();
+        let mut events = watcher(&source).with_cancel(cancel.clone());
+
+        assert!(events.poll().unwrap().is_empty());
+        source.set(&[9000]);
+        assert_eq!(events.next().unwrap().unwrap(), opened(9000));
+        source.set(&[]);
+        assert_eq!(events.next().unwrap().unwrap(), closed(9000));
+
+        cancel.cancel();
+        assert!(events.next().is_none());
+    }
+}